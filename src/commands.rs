@@ -0,0 +1,467 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{error, info};
+
+use crate::{
+    common::RestoreOptions,
+    config::{Config, ServiceConfig},
+    error::BusError,
+    manifest::{CatalogEntry, catalog},
+    pipeline::{decode_artifact, sha256_file},
+    retention::{Quota, Removal, plan_cleanup, plan_total_quota, remove_entry},
+    scheduler::BackupScheduler,
+    service::{STAGING_DIR, ServiceFactory},
+    storage::{Destination, fetch},
+};
+
+fn find_service<'a>(config: &'a Config, alias: &str) -> Result<&'a ServiceConfig, BusError> {
+    config
+        .services
+        .iter()
+        .find(|service| service.alias == alias)
+        .ok_or_else(|| BusError::Config(format!("Unknown service '{}'", alias)))
+}
+
+/// Selects the service `alias`, or every service.
+fn selected_services<'a>(
+    config: &'a Config,
+    alias: Option<&str>,
+) -> Result<Vec<&'a ServiceConfig>, BusError> {
+    match alias {
+        Some(alias) => Ok(vec![find_service(config, alias)?]),
+        None => Ok(config.services.iter().collect()),
+    }
+}
+
+fn destinations(config: &Config, service: &ServiceConfig) -> Result<Vec<Destination>, BusError> {
+    Destination::resolve(
+        &config.common,
+        service.destinations.as_ref(),
+        service.retention.as_ref(),
+    )
+}
+
+/// Decodes `artifact` into `output`, or stdout, with the keys of `alias` or `[common]`.
+pub async fn decrypt(
+    config: &Config,
+    artifact: &Path,
+    output: Option<&Path>,
+    alias: Option<&str>,
+) -> Result<(), BusError> {
+    let service = alias.map(|alias| find_service(config, alias)).transpose()?;
+    let keys = service
+        .and_then(|s| s.encryption.as_ref())
+        .or(config.common.encryption.as_ref());
+
+    // Artifacts that are no longer on this host are fetched from the service's destinations.
+    let mut fetched = None;
+    if let Some(service) = service
+        && !artifact.exists()
+    {
+        let name = artifact.to_string_lossy();
+        let target = fetch_target(config, &name).await?;
+        fetch(&destinations(config, service)?, &name, &target).await?;
+        fetched = Some(target);
+    }
+
+    let source = fetched.as_deref().unwrap_or(artifact);
+    let decoded = decode_artifact(source, output, keys).await;
+    if let Some(fetched) = &fetched {
+        let _ = tokio::fs::remove_file(fetched).await;
+    }
+
+    info!("Decoded {:?} ({} bytes)", artifact, decoded?);
+    Ok(())
+}
+
+/// Restores the backup named `backup`, or the latest one, of service `alias`.
+pub async fn restore(
+    config: &Config,
+    alias: &str,
+    backup: Option<&str>,
+    target_db: Option<&str>,
+) -> Result<(), BusError> {
+    let service = find_service(config, alias)?;
+    let job = ServiceFactory::create_service(service.clone(), &config.common)?;
+    if !job.describe().restore {
+        return Err(BusError::Unsupported(format!(
+            "restoring {} services",
+            service.service_type
+        )));
+    }
+
+    let (target, entry) = fetch_backup(config, service, backup).await?;
+    let options = RestoreOptions {
+        target_db: target_db.map(str::to_string),
+        ..restore_options(config, service, &entry)
+    };
+    let restored = job.restore(&target, &options).await;
+    let _ = tokio::fs::remove_file(&target).await;
+    restored?;
+
+    match target_db {
+        Some(target_db) => println!(
+            "Restored {} of '{}' into database '{}'",
+            entry.manifest.artifact, alias, target_db
+        ),
+        None => println!("Restored {} into '{}'", entry.manifest.artifact, alias),
+    }
+    Ok(())
+}
+
+/// Checks the backup named `backup`, or the latest one, of service `alias` the way the
+/// scheduler checks fresh backups; fails if it doesn't pass.
+pub async fn verify(config: &Config, alias: &str, backup: Option<&str>) -> Result<(), BusError> {
+    let service = find_service(config, alias)?;
+    let job = ServiceFactory::create_service(service.clone(), &config.common)?;
+    if !job.describe().verify {
+        return Err(BusError::Unsupported(format!(
+            "verifying '{}'; {} services need a [services.verify] table",
+            alias, service.service_type
+        )));
+    }
+
+    let (target, entry) = fetch_backup(config, service, backup).await?;
+    let verification = job
+        .verify(&target, &restore_options(config, service, &entry))
+        .await;
+    let _ = tokio::fs::remove_file(&target).await;
+
+    let Some(verification) = verification else {
+        return Err(BusError::Unsupported(format!("verifying '{}'", alias)));
+    };
+    if let Some(e) = verification.error {
+        return Err(BusError::Verification(format!(
+            "{} of '{}': {}",
+            entry.manifest.artifact, alias, e
+        )));
+    }
+
+    println!(
+        "Verified {} of '{}' in scratch database '{}': {} tables",
+        entry.manifest.artifact,
+        alias,
+        verification.database,
+        verification.tables.unwrap_or_default()
+    );
+    for (table, rows) in &verification.row_counts {
+        println!("  {}: {} rows", table, rows);
+    }
+    Ok(())
+}
+
+/// How to load `entry`: with the service's keys, else `[common]`'s, and the options it was
+/// taken with.
+fn restore_options(
+    config: &Config,
+    service: &ServiceConfig,
+    entry: &CatalogEntry,
+) -> RestoreOptions {
+    RestoreOptions {
+        keys: service
+            .encryption
+            .clone()
+            .or_else(|| config.common.encryption.clone()),
+        backup_options: entry.manifest.backup_options.clone(),
+        target_db: None,
+    }
+}
+
+/// A file in staging to fetch `artifact` into, which never overwrites the staged copy of a
+/// backup that is still waiting to be delivered.
+async fn fetch_target(config: &Config, artifact: &str) -> Result<PathBuf, BusError> {
+    let staging_dir = PathBuf::from(&config.common.backup_dir).join(STAGING_DIR);
+    tokio::fs::create_dir_all(&staging_dir)
+        .await
+        .map_err(|e| BusError::storage(&staging_dir, e))?;
+
+    Ok(staging_dir.join(format!(".fetch-{}-{}", std::process::id(), artifact)))
+}
+
+/// Copies the backup named `backup`, or the latest one, of `service` into staging from the
+/// first destination holding an intact copy of it. The caller removes the staged file.
+async fn fetch_backup(
+    config: &Config,
+    service: &ServiceConfig,
+    backup: Option<&str>,
+) -> Result<(PathBuf, CatalogEntry), BusError> {
+    let mut copies: Vec<(Destination, CatalogEntry)> = Vec::new();
+    for destination in destinations(config, service)? {
+        let entries = match catalog(
+            destination.backend.as_ref(),
+            &service.service_type,
+            &service.alias,
+        )
+        .await
+        {
+            Ok(entries) => entries,
+            // Another destination may still hold the backup.
+            Err(e) => {
+                error!("Skipping {}: {}", destination.backend, e);
+                continue;
+            }
+        };
+
+        let entry = match backup {
+            Some(backup) => entries
+                .into_iter()
+                .find(|entry| entry.manifest.artifact == backup),
+            None => entries.into_iter().last(),
+        };
+        if let Some(entry) = entry {
+            copies.push((destination, entry));
+        }
+    }
+
+    let Some(artifact) = copies
+        .iter()
+        .max_by_key(|(_, entry)| entry.manifest.finished_at)
+        .map(|(_, entry)| entry.manifest.artifact.clone())
+    else {
+        return Err(BusError::Config(match backup {
+            Some(backup) => format!(
+                "No backup '{}' of '{}'; see `bus list`",
+                backup, service.alias
+            ),
+            None => format!("No backups of '{}'", service.alias),
+        }));
+    };
+    copies.retain(|(_, entry)| entry.manifest.artifact == artifact);
+
+    let target = fetch_target(config, &artifact).await?;
+
+    let mut fetched = None;
+    for (destination, entry) in &copies {
+        match fetch_verified(destination, entry, &target).await {
+            Ok(()) => {
+                info!(
+                    "Fetched {} of '{}' from {}, taken {}",
+                    artifact, service.alias, destination.backend, entry.manifest.finished_at
+                );
+                fetched = Some(Ok(entry.clone()));
+                break;
+            }
+            Err(e) => {
+                error!("Cannot use the copy in {}: {}", destination.backend, e);
+                fetched = Some(Err(e));
+            }
+        }
+    }
+    match fetched.expect("at least one copy") {
+        Ok(entry) => Ok((target, entry)),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&target).await;
+            Err(e)
+        }
+    }
+}
+
+/// Copies the artifact of `entry` to `target`, refusing a copy that doesn't match its manifest.
+async fn fetch_verified(
+    destination: &Destination,
+    entry: &CatalogEntry,
+    target: &Path,
+) -> Result<(), BusError> {
+    destination
+        .backend
+        .get(&entry.manifest.artifact, target)
+        .await?;
+
+    // Never load a truncated or tampered artifact into a live service.
+    let sha256 = sha256_file(target).await?;
+    if sha256 != entry.manifest.sha256 {
+        return Err(BusError::remote(
+            &destination.backend,
+            format!(
+                "{} does not match its manifest (sha256 {}, expected {})",
+                entry.manifest.artifact, sha256, entry.manifest.sha256
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Backs up `alias`, or every service, once and returns; fails if any backup failed.
+pub async fn run_once(config: Config, alias: Option<&str>) -> Result<(), BusError> {
+    BackupScheduler::new(config)?.run_once(alias).await
+}
+
+/// Prints the backups Bus stored for each service, oldest first.
+pub async fn list(config: &Config, alias: Option<&str>) -> Result<(), BusError> {
+    for service in selected_services(config, alias)? {
+        println!("{} ({})", service.alias, service.service_type);
+
+        for destination in destinations(config, service)? {
+            println!("  {}", destination.backend);
+
+            let entries = catalog(
+                destination.backend.as_ref(),
+                &service.service_type,
+                &service.alias,
+            )
+            .await?;
+            if entries.is_empty() {
+                println!("    no backups");
+            }
+            for entry in entries {
+                println!(
+                    "    {}  {}  {} bytes",
+                    entry.manifest.finished_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    entry.manifest.artifact,
+                    entry.manifest.size
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints whether each service is reachable, what it supports and its latest backup in
+/// each of its destinations.
+pub async fn status(config: &Config, alias: Option<&str>) -> Result<(), BusError> {
+    for service in selected_services(config, alias)? {
+        let job = ServiceFactory::create_service(service.clone(), &config.common)?;
+        let capabilities = job.describe();
+        let health = match capabilities.health_check {
+            true => job
+                .health_check()
+                .await
+                .unwrap_or_else(|e| format!("unreachable: {}", e)),
+            false => "no health check".to_string(),
+        };
+        println!(
+            "{} ({}): {}; supports {}",
+            service.alias, service.service_type, health, capabilities
+        );
+
+        for destination in destinations(config, service)? {
+            let entries = match catalog(
+                destination.backend.as_ref(),
+                &service.service_type,
+                &service.alias,
+            )
+            .await
+            {
+                Ok(entries) => entries,
+                Err(e) => {
+                    println!("  {}: unavailable: {}", destination.backend, e);
+                    continue;
+                }
+            };
+
+            let total: u64 = entries.iter().map(|entry| entry.manifest.size).sum();
+            match entries.last() {
+                Some(latest) => println!(
+                    "  {}: {} backup(s), {} bytes; latest {} ({} bytes)",
+                    destination.backend,
+                    entries.len(),
+                    total,
+                    latest.manifest.finished_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    latest.manifest.size
+                ),
+                None => println!("  {}: no backups", destination.backend),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the configuration can be loaded and every service, schedule and destination
+/// can be set up, without connecting to anything.
+pub fn validate(config: Config) -> Result<(), BusError> {
+    let services = config.services.len();
+    BackupScheduler::new(config)?;
+
+    println!("Configuration is valid ({} service(s))", services);
+    Ok(())
+}
+
+/// Removes what the scheduler's cleanup would remove right now and prints each backup.
+pub async fn prune(config: &Config, alias: Option<&str>, dry_run: bool) -> Result<(), BusError> {
+    if let Some(alias) = alias {
+        find_service(config, alias)?;
+    }
+
+    // Services sharing a destination are planned together, so the total quota sees all of them.
+    let mut plans: Vec<(Destination, Vec<Removal>)> = Vec::new();
+    for service in &config.services {
+        if alias.is_some_and(|alias| alias != service.alias) {
+            continue;
+        }
+
+        let quota = Quota::from_config(service.quota.as_ref())?;
+        let mut destinations = Destination::resolve(
+            &config.common,
+            service.destinations.as_ref(),
+            service.retention.as_ref(),
+        )?;
+        destinations.push(Destination::staging(
+            &config.common,
+            service.retention.as_ref(),
+        )?);
+
+        for destination in destinations {
+            let removals = plan_cleanup(
+                &destination,
+                &service.service_type,
+                &service.alias,
+                quota.as_ref(),
+            )
+            .await?;
+
+            let location = destination.backend.to_string();
+            match plans
+                .iter_mut()
+                .find(|(planned, _)| planned.backend.to_string() == location)
+            {
+                Some((_, planned)) => planned.extend(removals),
+                None => plans.push((destination, removals)),
+            }
+        }
+    }
+
+    if alias.is_none()
+        && let Some(quota) = Quota::from_config(config.common.quota.as_ref())?
+    {
+        for (destination, planned) in &mut plans {
+            let removals = plan_total_quota(destination, &quota, planned).await?;
+            planned.extend(removals);
+        }
+    }
+
+    let mut failed = 0;
+    for (destination, removals) in &plans {
+        for Removal { entry, reason } in removals {
+            let action = if dry_run {
+                "Would remove"
+            } else if let Err(e) = remove_entry(destination, entry).await {
+                error!("Failed to remove {}: {}", entry.manifest.artifact, e);
+                failed += 1;
+                continue;
+            } else {
+                "Removed"
+            };
+
+            println!(
+                "{} {} from {} ({}, {} bytes)",
+                action, entry.manifest.artifact, destination.backend, reason, entry.manifest.size
+            );
+        }
+    }
+
+    if plans.iter().all(|(_, removals)| removals.is_empty()) {
+        println!("Nothing to prune");
+    }
+
+    if failed > 0 {
+        return Err(BusError::Retention(format!(
+            "Failed to remove {} backup(s)",
+            failed
+        )));
+    }
+
+    Ok(())
+}
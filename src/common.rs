@@ -1,16 +1,110 @@
 #![allow(dead_code)]
 
-use crate::config::{ScheduleConfig, ServiceType};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    path::Path,
+};
 
+use serde::Serialize;
+
+use crate::{
+    config::{ScheduleConfig, ServiceType},
+    encryption::EncryptionConfig,
+    error::BusError,
+    pipeline::Artifact,
+};
+
+/// What `bus restore` hands a service along with the artifact.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Keys to decrypt the artifact with.
+    pub keys: Option<EncryptionConfig>,
+    /// The options the backup was taken with, as recorded in its manifest.
+    pub backup_options: BTreeMap<String, String>,
+    /// Restores into this database instead of the configured one.
+    pub target_db: Option<String>,
+}
+
+/// The outcome of restoring a fresh backup into a scratch database, recorded in its manifest.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Verification {
+    pub passed: bool,
+    pub database: String,
+    /// Tables found in the restored database.
+    pub tables: Option<u64>,
+    pub row_counts: BTreeMap<String, u64>,
+    /// Why the check failed.
+    pub error: Option<String>,
+    pub duration_seconds: f64,
+}
+
+/// What a service does besides taking backups, so commands can tell before they start.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    pub restore: bool,
+    /// Whether backups are verified, which needs the service's `verify` table.
+    pub verify: bool,
+    pub health_check: bool,
+}
+
+/// Backups, plus the hooks a service type opts into: the defaults report them unsupported,
+/// so a new service type only needs `backup` and its getters to be scheduled.
 #[async_trait::async_trait]
 pub trait BackupService: Send + Sync {
-    async fn backup(
-        &self,
-        timestamp: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+    async fn backup(&self, timestamp: &str) -> Result<Artifact, BusError>;
+
+    /// Loads a local artifact of this service back into it.
+    async fn restore(&self, _artifact: &Path, _options: &RestoreOptions) -> Result<(), BusError> {
+        Err(BusError::Unsupported(format!(
+            "restoring {} services",
+            self.service_type()
+        )))
+    }
+
+    /// Restores an artifact somewhere disposable and checks it; `None` when the service
+    /// isn't configured to.
+    async fn verify(&self, _artifact: &Path, _options: &RestoreOptions) -> Option<Verification> {
+        None
+    }
+
+    /// Checks the service can be reached and describes it, e.g. with its version.
+    async fn health_check(&self) -> Result<String, BusError> {
+        Err(BusError::Unsupported(format!(
+            "health checks of {} services",
+            self.service_type()
+        )))
+    }
+
+    fn describe(&self) -> Capabilities {
+        Capabilities::default()
+    }
 
     fn get_schedule(&self) -> &ScheduleConfig;
     fn alias(&self) -> &str;
-    fn backup_dir(&self) -> &str;
     fn service_type(&self) -> &ServiceType;
+    fn backup_options(&self) -> Option<&HashMap<String, String>>;
+
+    /// Whether a failed backup should be retried; services can refine the default classification.
+    fn is_retryable(&self, error: &BusError) -> bool {
+        error.is_retryable()
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [
+            (self.restore, "restore"),
+            (self.verify, "verify"),
+            (self.health_check, "health check"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect();
+
+        match names.is_empty() {
+            true => write!(f, "backup only"),
+            false => write!(f, "backup, {}", names.join(", ")),
+        }
+    }
 }
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Read},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::BusError, pipeline::FinishWrite};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompressionConfig {
+    pub codec: Codec,
+    pub level: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: i32,
+}
+
+impl FinishWrite for flate2::write::GzEncoder<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+impl FinishWrite for zstd::stream::write::Encoder<'static, Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+impl FinishWrite for xz2::write::XzEncoder<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+/// `backup_options` keys every service type accepts.
+pub const BACKUP_OPTIONS: [&str; 2] = ["compression", "compression_level"];
+
+impl Compression {
+    /// Per-service `backup_options` (`compression`, `compression_level`) override `[common.compression]`.
+    pub fn resolve(
+        common: Option<&CompressionConfig>,
+        backup_options: Option<&HashMap<String, String>>,
+    ) -> Result<Self, BusError> {
+        let option = |key: &str| backup_options.and_then(|options| options.get(key));
+
+        let codec = match option("compression") {
+            Some(name) => Codec::parse(name)?,
+            None => common.map(|c| c.codec).unwrap_or(Codec::Gzip),
+        };
+
+        let level =
+            match option("compression_level") {
+                Some(level) => Some(level.parse::<i32>().map_err(|_| {
+                    BusError::Config(format!("Invalid compression_level '{}'", level))
+                })?),
+                None => common.filter(|c| c.codec == codec).and_then(|c| c.level),
+            };
+
+        Self::new(codec, level)
+    }
+
+    pub fn new(codec: Codec, level: Option<i32>) -> Result<Self, BusError> {
+        let (range, default) = match codec {
+            Codec::None => (0..=0, 0),
+            Codec::Gzip => (0..=9, 6),
+            Codec::Zstd => (1..=22, 3),
+            Codec::Xz => (0..=9, 6),
+        };
+
+        let level = level.unwrap_or(default);
+        if !range.contains(&level) {
+            return Err(BusError::Config(format!(
+                "Compression level {} is out of range {}..={} for {}",
+                level,
+                range.start(),
+                range.end(),
+                codec
+            )));
+        }
+
+        Ok(Self { codec, level })
+    }
+
+    /// File extension appended to compressed artifacts, without the leading dot.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self.codec {
+            Codec::None => None,
+            Codec::Gzip => Some("gz"),
+            Codec::Zstd => Some("zst"),
+            Codec::Xz => Some("xz"),
+        }
+    }
+
+    pub fn encoder(&self, writer: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>, BusError> {
+        let encoder: Box<dyn FinishWrite> = match self.codec {
+            Codec::None => writer,
+            Codec::Gzip => Box::new(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(self.level as u32),
+            )),
+            Codec::Zstd => Box::new(
+                zstd::stream::write::Encoder::new(writer, self.level)
+                    .map_err(|e| BusError::Compression(e.to_string()))?,
+            ),
+            Codec::Xz => Box::new(xz2::write::XzEncoder::new(writer, self.level as u32)),
+        };
+
+        Ok(encoder)
+    }
+}
+
+/// Undoes the compression stage named by an artifact's `extension` (`gz`, `zst` or `xz`).
+pub fn decoder(
+    extension: &str,
+    reader: Box<dyn Read + Send>,
+) -> Result<Box<dyn Read + Send>, BusError> {
+    let decoder: Box<dyn Read + Send> = match extension {
+        "gz" => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        "zst" => Box::new(
+            zstd::stream::read::Decoder::new(reader)
+                .map_err(|e| BusError::Compression(e.to_string()))?,
+        ),
+        "xz" => Box::new(xz2::read::XzDecoder::new(reader)),
+        _ => reader,
+    };
+
+    Ok(decoder)
+}
+
+impl Codec {
+    pub fn parse(name: &str) -> Result<Self, BusError> {
+        match name {
+            "none" => Ok(Codec::None),
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            "xz" => Ok(Codec::Xz),
+            _ => Err(BusError::Config(format!(
+                "Unknown compression codec '{}', expected none, gzip, zstd or xz",
+                name
+            ))),
+        }
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Codec::None => write!(f, "none"),
+            Codec::Gzip => write!(f, "gzip"),
+            Codec::Zstd => write!(f, "zstd"),
+            Codec::Xz => write!(f, "xz"),
+        }
+    }
+}
@@ -2,8 +2,13 @@ use std::{collections::HashMap, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
+use crate::compression::CompressionConfig;
+use crate::encryption::EncryptionConfig;
+use crate::service::command::config::CommandConnectionConfig;
+use crate::service::mysql::config::MySqlConnectionConfig;
 use crate::service::postgres::config::PostgresConnectionConfig;
 use crate::service::redis::config::RedisConnectionConfig;
+use crate::storage::DestinationConfig;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
@@ -17,6 +22,14 @@ pub struct CommonConfig {
     pub log_level: Option<String>,
     pub log_dir: Option<String>,
     pub retention_days: Option<i64>,
+    /// Replaces the `retention_days` age cutoff with a grandfather-father-son policy.
+    pub retention: Option<RetentionConfig>,
+    /// Caps the backups of all services together in each destination.
+    pub quota: Option<QuotaConfig>,
+    pub compression: Option<CompressionConfig>,
+    pub encryption: Option<EncryptionConfig>,
+    /// Where finished artifacts are stored; defaults to `backup_dir` alone.
+    pub destinations: Option<Vec<DestinationConfig>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -28,6 +41,17 @@ pub struct ServiceConfig {
     #[serde(deserialize_with = "deserialize_connection")]
     pub connection: ConnectionConfig,
     pub backup_options: Option<HashMap<String, String>>,
+    /// How `bus restore` restores this service; the keys depend on the service type.
+    pub restore_options: Option<HashMap<String, String>>,
+    pub encryption: Option<EncryptionConfig>,
+    /// Replaces `common.destinations` for this service.
+    pub destinations: Option<Vec<DestinationConfig>>,
+    /// Replaces `common.retention` for this service.
+    pub retention: Option<RetentionConfig>,
+    /// Caps the backups of this service in each destination.
+    pub quota: Option<QuotaConfig>,
+    /// Restores every backup into a scratch database and checks it before it is stored.
+    pub verify: Option<VerifyConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -35,6 +59,8 @@ pub struct ServiceConfig {
 pub enum ServiceType {
     Postgres,
     Redis,
+    Command,
+    MySql,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -42,6 +68,8 @@ pub enum ServiceType {
 pub enum ConnectionConfig {
     Postgres(PostgresConnectionConfig),
     Redis(RedisConnectionConfig),
+    Command(CommandConnectionConfig),
+    MySql(MySqlConnectionConfig),
 }
 
 fn deserialize_connection<'de, D>(deserializer: D) -> Result<ConnectionConfig, D::Error>
@@ -64,13 +92,23 @@ where
                     serde_json::from_value(value).map_err(D::Error::custom)?;
                 Ok(ConnectionConfig::Redis(config))
             }
+            "command" => {
+                let config: CommandConnectionConfig =
+                    serde_json::from_value(value).map_err(D::Error::custom)?;
+                Ok(ConnectionConfig::Command(config))
+            }
+            "mysql" => {
+                let config: MySqlConnectionConfig =
+                    serde_json::from_value(value).map_err(D::Error::custom)?;
+                Ok(ConnectionConfig::MySql(config))
+            }
             _ => Err(D::Error::custom(format!(
                 "Unknown service type: {}",
                 service_type
             ))),
         }
     } else {
-        Err(D::Error::missing_field("type"))
+        Err(D::Error::missing_field("service_type"))
     }
 }
 
@@ -89,6 +127,20 @@ impl ConnectionConfig {
             _ => None,
         }
     }
+
+    pub fn as_command(&self) -> Option<&CommandConnectionConfig> {
+        match self {
+            ConnectionConfig::Command(config) => Some(config),
+            _ => None,
+        }
+    }
+
+    pub fn as_mysql(&self) -> Option<&MySqlConnectionConfig> {
+        match self {
+            ConnectionConfig::MySql(config) => Some(config),
+            _ => None,
+        }
+    }
 }
 
 impl Display for ServiceConfig {
@@ -106,6 +158,8 @@ impl Display for ServiceType {
         match self {
             ServiceType::Postgres => write!(f, "postgres"),
             ServiceType::Redis => write!(f, "redis"),
+            ServiceType::Command => write!(f, "command"),
+            ServiceType::MySql => write!(f, "mysql"),
         }
     }
 }
@@ -114,15 +168,102 @@ impl Display for ScheduleConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "ScheduleConfig {{ interval_seconds: {}, timezone: {:?}, start_time: {:?} }}",
-            self.interval_seconds, self.timezone, self.start_time
+            "ScheduleConfig {{ interval_seconds: {:?}, cron: {:?}, timezone: {:?}, start_time: {:?} }}",
+            self.interval_seconds, self.cron, self.timezone, self.start_time
         )
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ScheduleConfig {
-    pub interval_seconds: u64,
+    pub interval_seconds: Option<u64>,
+    pub cron: Option<String>,
     pub timezone: Option<String>,
     pub start_time: Option<String>,
+    pub retry: Option<RetryConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_initial_delay_seconds")]
+    pub initial_delay_seconds: u64,
+    #[serde(default = "default_retry_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "default_retry_max_delay_seconds")]
+    pub max_delay_seconds: u64,
+    /// Fraction of each delay randomly added or removed, e.g. `0.1` for +/-10%.
+    #[serde(default = "default_retry_jitter")]
+    pub jitter: f64,
+}
+
+/// Numbers of backups to keep; a backup is kept if any rule keeps it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// The most recent backups, regardless of when they ran.
+    pub keep_last: u32,
+    /// The newest backup of each of the most recent hours, days, ... (UTC).
+    pub keep_hourly: u32,
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    pub keep_monthly: u32,
+    pub keep_yearly: u32,
+}
+
+/// Prunes the oldest backups once the stored total exceeds `max_total_bytes`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QuotaConfig {
+    pub max_total_bytes: u64,
+    /// Newest backups of each service that are kept even when over quota.
+    #[serde(default = "default_quota_min_keep")]
+    pub min_keep: u32,
+}
+
+/// Where and how a backup is checked after it is taken. The scratch database is created
+/// before and dropped after every check, so the user needs `CREATEDB` there.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VerifyConfig {
+    /// Server to restore into with the service's credentials; defaults to the service's own.
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Defaults to `bus_verify_<alias>`.
+    pub database: Option<String>,
+    /// Fewest tables the restored database must have.
+    #[serde(default = "default_verify_min_tables")]
+    pub min_tables: u64,
+    /// Tables whose rows are counted; each must exist.
+    #[serde(default)]
+    pub tables: Vec<String>,
+    /// A query that must return true, e.g. `SELECT count(*) > 0 FROM users`.
+    pub sql: Option<String>,
+}
+
+fn default_verify_min_tables() -> u64 {
+    1
+}
+
+fn default_quota_min_keep() -> u32 {
+    1
+}
+
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_initial_delay_seconds() -> u64 {
+    30
+}
+
+fn default_retry_multiplier() -> f64 {
+    2.0
+}
+
+fn default_retry_max_delay_seconds() -> u64 {
+    600
+}
+
+fn default_retry_jitter() -> f64 {
+    0.1
 }
@@ -0,0 +1,401 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{
+        Payload,
+        stream::{DecryptorBE32, EncryptorBE32},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::BusError, pipeline::FinishWrite, secret::Secret};
+
+const MAGIC: &[u8; 8] = b"BUSAES1\n";
+const SALT_LEN: usize = 16;
+/// AES-GCM's 12-byte nonce minus the 5 bytes STREAM uses for its counter and last-chunk flag.
+const NONCE_PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + 3 * 4 + SALT_LEN + NONCE_PREFIX_LEN;
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum EncryptionConfig {
+    /// Explicitly disables encryption, e.g. for one service when `[common.encryption]` is set.
+    None,
+    Age {
+        /// X25519 public keys (`age1...`) that can decrypt the backups.
+        recipients: Vec<String>,
+        /// Private key file used when restoring; not needed for taking backups.
+        identity_file: Option<String>,
+    },
+    Passphrase {
+        passphrase: Secret,
+    },
+}
+
+#[derive(Clone)]
+pub enum Encryption {
+    Age(Vec<age::x25519::Recipient>),
+    Passphrase(Secret),
+}
+
+impl Encryption {
+    /// A service's own `encryption` table replaces `[common.encryption]` entirely.
+    pub fn resolve(
+        common: Option<&EncryptionConfig>,
+        service: Option<&EncryptionConfig>,
+    ) -> Result<Option<Self>, BusError> {
+        match service.or(common) {
+            None | Some(EncryptionConfig::None) => Ok(None),
+            Some(EncryptionConfig::Age { recipients, .. }) => {
+                if recipients.is_empty() {
+                    return Err(BusError::Config(
+                        "age encryption needs at least one recipient".into(),
+                    ));
+                }
+
+                let recipients = recipients
+                    .iter()
+                    .map(|recipient| {
+                        age::x25519::Recipient::from_str(recipient).map_err(|e| {
+                            BusError::Config(format!(
+                                "Invalid age recipient '{}': {}",
+                                recipient, e
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Some(Encryption::Age(recipients)))
+            }
+            Some(EncryptionConfig::Passphrase { passphrase }) => {
+                if passphrase.is_empty() {
+                    return Err(BusError::Config("Encryption passphrase is empty".into()));
+                }
+
+                Ok(Some(Encryption::Passphrase(passphrase.clone())))
+            }
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encryption::Age(_) => "age",
+            Encryption::Passphrase(_) => "enc",
+        }
+    }
+
+    /// Blocking, as it may resolve the passphrase; call from `spawn_blocking`.
+    pub fn encryptor(&self, inner: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>, BusError> {
+        match self {
+            Encryption::Age(recipients) => {
+                let encryptor = age::Encryptor::with_recipients(
+                    recipients.iter().map(|r| r as &dyn age::Recipient),
+                )
+                .map_err(|e| BusError::Encryption(e.to_string()))?;
+
+                let writer = encryptor
+                    .wrap_output(inner)
+                    .map_err(|e| BusError::Encryption(e.to_string()))?;
+
+                Ok(Box::new(writer))
+            }
+            Encryption::Passphrase(passphrase) => {
+                let passphrase = passphrase.resolve_blocking()?;
+                Ok(Box::new(PassphraseWriter::new(&passphrase, inner)?))
+            }
+        }
+    }
+}
+
+impl Display for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encryption::Age(recipients) => write!(f, "age ({} recipients)", recipients.len()),
+            Encryption::Passphrase(_) => write!(f, "aes-256-gcm"),
+        }
+    }
+}
+
+/// Serialized into backup manifests; records the method and public recipients, never key material.
+impl Serialize for Encryption {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(tag = "method", rename_all = "lowercase")]
+        enum Settings {
+            Age { recipients: Vec<String> },
+            Passphrase { cipher: &'static str },
+        }
+
+        match self {
+            Encryption::Age(recipients) => Settings::Age {
+                recipients: recipients.iter().map(ToString::to_string).collect(),
+            },
+            Encryption::Passphrase(_) => Settings::Passphrase {
+                cipher: "aes-256-gcm",
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Encryption({})", self)
+    }
+}
+
+impl FinishWrite for age::stream::StreamWriter<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+/// Undoes the encryption stage named by an artifact's `extension` (`age` or `enc`).
+pub fn decryptor(
+    extension: &str,
+    keys: Option<&EncryptionConfig>,
+    reader: Box<dyn Read + Send>,
+) -> Result<Box<dyn Read + Send>, BusError> {
+    match (extension, keys) {
+        (
+            "age",
+            Some(EncryptionConfig::Age {
+                identity_file: Some(identity_file),
+                ..
+            }),
+        ) => {
+            let identities = age::IdentityFile::from_file(identity_file.clone())
+                .map_err(|e| {
+                    BusError::Encryption(format!("Failed to read {}: {}", identity_file, e))
+                })?
+                .into_identities()
+                .map_err(|e| BusError::Encryption(e.to_string()))?;
+
+            let decryptor =
+                age::Decryptor::new(reader).map_err(|e| BusError::Encryption(e.to_string()))?;
+            let reader = decryptor
+                .decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity))
+                .map_err(|e| BusError::Encryption(e.to_string()))?;
+
+            Ok(Box::new(reader))
+        }
+        ("age", _) => Err(BusError::Encryption(
+            "Decrypting an age artifact needs 'identity_file' in the encryption config".into(),
+        )),
+        ("enc", Some(EncryptionConfig::Passphrase { passphrase })) => {
+            let passphrase = passphrase.resolve_blocking()?;
+            Ok(Box::new(PassphraseReader::new(&passphrase, reader)?))
+        }
+        ("enc", _) => Err(BusError::Encryption(
+            "Decrypting an .enc artifact needs the passphrase encryption config".into(),
+        )),
+        _ => Ok(reader),
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: argon2::Params,
+) -> Result<Aes256Gcm, BusError> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| BusError::Encryption(format!("Key derivation failed: {}", e)))?;
+
+    Aes256Gcm::new_from_slice(&key).map_err(|e| BusError::Encryption(e.to_string()))
+}
+
+/// Chunked AES-256-GCM (STREAM construction) with an Argon2id-derived key.
+///
+/// Layout: magic, Argon2 m/t/p costs (LE u32), salt, nonce prefix, then 64 KiB plaintext chunks
+/// each followed by a 16-byte tag. The last chunk is always shorter than a full one and is
+/// flagged as final, so truncation is detected. The header is authenticated with every chunk.
+struct PassphraseWriter {
+    inner: Box<dyn FinishWrite>,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl PassphraseWriter {
+    fn new(passphrase: &str, mut inner: Box<dyn FinishWrite>) -> Result<Self, BusError> {
+        let params = argon2::Params::default();
+        let salt: [u8; SALT_LEN] = rand::random();
+        let nonce: [u8; NONCE_PREFIX_LEN] = rand::random();
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&params.m_cost().to_le_bytes());
+        header.extend_from_slice(&params.t_cost().to_le_bytes());
+        header.extend_from_slice(&params.p_cost().to_le_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+
+        let cipher = derive_key(passphrase, &salt, params)?;
+        inner
+            .write_all(&header)
+            .map_err(|e| BusError::Encryption(e.to_string()))?;
+
+        Ok(Self {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, nonce[..].into())),
+            header,
+            buffer: Vec::with_capacity(CHUNK_LEN),
+        })
+    }
+
+    fn seal_full_chunks(&mut self) -> io::Result<()> {
+        while self.buffer.len() >= CHUNK_LEN {
+            let encryptor = self.encryptor.as_mut().expect("writer not finished");
+            let sealed = encryptor
+                .encrypt_next(Payload {
+                    msg: &self.buffer[..CHUNK_LEN],
+                    aad: &self.header,
+                })
+                .map_err(|_| io::Error::other("AES-GCM encryption failed"))?;
+
+            self.inner.write_all(&sealed)?;
+            self.buffer.drain(..CHUNK_LEN);
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for PassphraseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.seal_full_chunks()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl FinishWrite for PassphraseWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.seal_full_chunks()?;
+
+        let encryptor = self.encryptor.take().expect("writer not finished");
+        let sealed = encryptor
+            .encrypt_last(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })
+            .map_err(|_| io::Error::other("AES-GCM encryption failed"))?;
+
+        self.inner.write_all(&sealed)?;
+        self.inner.finish()
+    }
+}
+
+struct PassphraseReader {
+    inner: Box<dyn Read + Send>,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    header: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl PassphraseReader {
+    fn new(passphrase: &str, mut inner: Box<dyn Read + Send>) -> Result<Self, BusError> {
+        let mut header = vec![0u8; HEADER_LEN];
+        inner
+            .read_exact(&mut header)
+            .map_err(|e| BusError::Encryption(format!("Failed to read header: {}", e)))?;
+
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(BusError::Encryption(
+                "Not a Bus passphrase-encrypted artifact".into(),
+            ));
+        }
+
+        let cost = |index: usize| {
+            let start = MAGIC.len() + index * 4;
+            u32::from_le_bytes(header[start..start + 4].try_into().expect("4 bytes"))
+        };
+        let params = argon2::Params::new(cost(0), cost(1), cost(2), None)
+            .map_err(|e| BusError::Encryption(format!("Invalid key derivation header: {}", e)))?;
+
+        let salt_start = MAGIC.len() + 12;
+        let salt = &header[salt_start..salt_start + SALT_LEN];
+        let nonce = &header[salt_start + SALT_LEN..];
+
+        let cipher = derive_key(passphrase, salt, params)?;
+        let decryptor = DecryptorBE32::from_aead(cipher, nonce.into());
+
+        Ok(Self {
+            inner,
+            decryptor: Some(decryptor),
+            header,
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    fn open_next_chunk(&mut self) -> io::Result<()> {
+        let mut sealed = vec![0u8; CHUNK_LEN + TAG_LEN];
+        let mut filled = 0;
+        while filled < sealed.len() {
+            match self.inner.read(&mut sealed[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        sealed.truncate(filled);
+
+        let payload = Payload {
+            msg: &sealed,
+            aad: &self.header,
+        };
+        let opened = if filled == CHUNK_LEN + TAG_LEN {
+            self.decryptor
+                .as_mut()
+                .expect("stream not finished")
+                .decrypt_next(payload)
+        } else {
+            self.decryptor
+                .take()
+                .expect("stream not finished")
+                .decrypt_last(payload)
+        };
+
+        self.plaintext = opened.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Decryption failed: wrong passphrase or corrupted artifact",
+            )
+        })?;
+        self.position = 0;
+
+        Ok(())
+    }
+}
+
+impl Read for PassphraseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.open_next_chunk()?;
+        }
+
+        let available = &self.plaintext[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
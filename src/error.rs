@@ -0,0 +1,148 @@
+use std::process::Output;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusError {
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Connection to '{service}' failed: {message}")]
+    Connection { service: String, message: String },
+
+    #[error("{tool} failed{}: {stderr}", exit_code.map(|code| format!(" with exit code {}", code)).unwrap_or_default())]
+    DumpTool {
+        tool: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+
+    /// A command killed for running past its `timeout_seconds`; never retried.
+    #[error("{tool} timed out after {seconds} seconds")]
+    ToolTimeout { tool: String, seconds: u64 },
+
+    #[error("Compression failed: {0}")]
+    Compression(String),
+
+    #[error("Encryption failed: {0}")]
+    Encryption(String),
+
+    #[error("Storage I/O failed for {path}: {source}")]
+    Storage {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Remote storage {location} failed: {message}")]
+    Remote { location: String, message: String },
+
+    #[error("Retention failed: {0}")]
+    Retention(String),
+
+    #[error("Failed to resolve secret: {0}")]
+    Secret(String),
+
+    /// Something a service type doesn't implement, e.g. restoring it.
+    #[error("Not supported: {0}")]
+    Unsupported(String),
+
+    #[error("Verification failed: {0}")]
+    Verification(String),
+
+    /// Backups that failed after all attempts, for callers that run them to completion.
+    #[error("Backup failed for {}", .0.join(", "))]
+    Backup(Vec<String>),
+}
+
+impl BusError {
+    /// Stable, machine-friendly name of the error category, used as a log field for alerting.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BusError::Config(_) => "config",
+            BusError::Connection { .. } => "connection",
+            BusError::DumpTool { .. } => "dump_tool",
+            BusError::ToolTimeout { .. } => "timeout",
+            BusError::Compression(_) => "compression",
+            BusError::Encryption(_) => "encryption",
+            BusError::Storage { .. } => "storage",
+            BusError::Remote { .. } => "remote",
+            BusError::Retention(_) => "retention",
+            BusError::Secret(_) => "secret",
+            BusError::Unsupported(_) => "unsupported",
+            BusError::Verification(_) => "verification",
+            BusError::Backup(_) => "backup",
+        }
+    }
+
+    /// Whether another attempt has a reasonable chance of succeeding.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Secret files may not be mounted yet and helpers may be briefly unreachable.
+            BusError::Connection { .. } | BusError::Remote { .. } | BusError::Secret(_) => true,
+            // No exit code means the tool never started (e.g. not installed) or was killed.
+            BusError::DumpTool { exit_code, .. } => exit_code.is_some(),
+            // A tool that hung once will most likely hang again, and every attempt would
+            // hold up the next backups for the whole timeout.
+            BusError::ToolTimeout { .. } => false,
+            BusError::Storage { source, .. } => !matches!(
+                source.kind(),
+                std::io::ErrorKind::StorageFull
+                    | std::io::ErrorKind::PermissionDenied
+                    | std::io::ErrorKind::ReadOnlyFilesystem
+            ),
+            BusError::Config(_)
+            | BusError::Compression(_)
+            | BusError::Encryption(_)
+            | BusError::Retention(_)
+            | BusError::Unsupported(_)
+            | BusError::Verification(_)
+            | BusError::Backup(_) => false,
+        }
+    }
+
+    pub fn connection(service: &str, error: impl std::fmt::Display) -> Self {
+        BusError::Connection {
+            service: service.to_string(),
+            message: error.to_string(),
+        }
+    }
+
+    /// A dump tool that could not be started at all, e.g. because it is not installed.
+    pub fn tool_unavailable(tool: &str, error: std::io::Error) -> Self {
+        BusError::DumpTool {
+            tool: tool.to_string(),
+            exit_code: None,
+            stderr: format!("failed to start: {}", error),
+        }
+    }
+
+    /// A dump tool that was killed because it ran longer than allowed.
+    pub fn tool_timed_out(tool: &str, limit: std::time::Duration) -> Self {
+        BusError::ToolTimeout {
+            tool: tool.to_string(),
+            seconds: limit.as_secs(),
+        }
+    }
+
+    /// A dump tool that ran and exited unsuccessfully.
+    pub fn tool_failed(tool: &str, output: &Output) -> Self {
+        BusError::DumpTool {
+            tool: tool.to_string(),
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    }
+
+    pub fn remote(location: impl std::fmt::Display, error: impl std::fmt::Display) -> Self {
+        BusError::Remote {
+            location: location.to_string(),
+            message: error.to_string(),
+        }
+    }
+
+    pub fn storage(path: impl AsRef<std::path::Path>, source: std::io::Error) -> Self {
+        BusError::Storage {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+}
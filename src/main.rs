@@ -1,15 +1,32 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
 use tracing::info;
 
-use crate::{config::Config, scheduler::BackupScheduler, utils::make_logger};
+use crate::{config::Config, error::BusError, scheduler::BackupScheduler, utils::make_logger};
 
+mod commands;
 mod common;
+mod compression;
 mod config;
+mod encryption;
+mod error;
+mod manifest;
+mod pipeline;
+mod retention;
 mod scheduler;
+mod secret;
 mod service;
+mod storage;
 mod utils;
+mod validation;
+
+#[cfg(test)]
+mod test;
 
 #[derive(Parser)]
 #[command(name = "cron")]
@@ -17,36 +34,209 @@ mod utils;
 struct Cli {
     #[arg(short, long)]
     config: PathBuf,
-    #[arg(short, long)]
+    /// Prefix of the log file names
+    #[arg(short, long, default_value = "bus")]
     prefix: String,
+    /// Defaults to `daemon`
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the scheduler and back up every service on its schedule
+    Daemon,
+    /// Back up one service right away and exit
+    Run {
+        /// Alias of the service
+        alias: String,
+    },
+    /// Back up every service once and exit; fails if any backup fails
+    Once,
+    /// List the stored backups of each service
+    List {
+        /// Only list this service
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+    /// Show whether each service is reachable and its latest backup in each destination
+    Status {
+        /// Only show this service
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+    /// Check the configuration file and exit
+    Validate,
+    /// Decrypt and decompress a backup artifact back into the original dump
+    Decrypt {
+        /// Local path, or with --service the name of an artifact in one of its destinations
+        artifact: PathBuf,
+        /// Where to write the dump; defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Use this service's encryption keys and destinations instead of the [common] ones
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+    /// Restore a backup of a service from its destinations
+    #[command(group(clap::ArgGroup::new("which").required(true).args(["backup", "latest"])))]
+    Restore {
+        /// The service to restore
+        #[arg(short, long)]
+        service: String,
+        /// The backup to restore, as shown by `list`
+        #[arg(long)]
+        backup: Option<String>,
+        /// Restore the most recent backup
+        #[arg(long)]
+        latest: bool,
+        /// Restore into this database instead of the configured one (Postgres)
+        #[arg(long)]
+        target_db: Option<String>,
+    },
+    /// Restore a stored backup into a scratch database and check it, as after each backup
+    #[command(group(clap::ArgGroup::new("which").required(true).args(["backup", "latest"])))]
+    Verify {
+        /// The service whose backup to verify
+        #[arg(short, long)]
+        service: String,
+        /// The backup to verify, as shown by `list`
+        #[arg(long)]
+        backup: Option<String>,
+        /// Verify the most recent backup
+        #[arg(long)]
+        latest: bool,
+    },
+    /// Apply retention and quotas now, as the scheduler does after each backup
+    Prune {
+        /// Only print the backups that would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Only prune this service; skips `common.quota`, which spans all services
+        #[arg(short, long)]
+        service: Option<String>,
+    },
+}
+
+impl Command {
+    fn keeps_log(&self) -> bool {
+        match self {
+            Command::Daemon
+            | Command::Run { .. }
+            | Command::Once
+            | Command::Restore { .. }
+            | Command::Verify { .. }
+            | Command::Prune { .. } => true,
+            Command::List { .. }
+            | Command::Status { .. }
+            | Command::Validate
+            | Command::Decrypt { .. } => false,
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn main() -> ExitCode {
     dotenvy::dotenv().ok();
 
     let cli = Cli::parse();
 
-    let content = tokio::fs::read_to_string(&cli.config)
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn load_config(path: &Path) -> Result<Config, BusError> {
+    let content = tokio::fs::read_to_string(path)
         .await
-        .expect("Failed to read config file");
+        .map_err(|e| BusError::storage(path, e))?;
 
-    let config: Config =
-        toml::from_str(content.as_str()).expect("[toml]: Failed to parse config file");
+    parse_config(path, &content)
+}
+
+/// Expands environment variables in every string, then deserializes and validates the
+/// configuration read from `path`.
+fn parse_config(path: &Path, content: &str) -> Result<Config, BusError> {
+    let parse_error = |e: &dyn std::fmt::Display| {
+        BusError::Config(format!("Failed to parse {}: {}", path.display(), e))
+    };
 
+    let mut value: toml::Value = toml::from_str(content).map_err(|e| parse_error(&e))?;
+
+    let problems = utils::substitute_config_env_vars(&mut value);
+    if !problems.is_empty() {
+        return Err(BusError::Config(validation::report(
+            path, content, &problems,
+        )));
+    }
+
+    let config = Config::deserialize(value).map_err(|e| {
+        // The same error found in the file as written also points at its line.
+        match toml::from_str::<Config>(content) {
+            Err(spanned) if spanned.message() == e.message() => parse_error(&spanned),
+            _ => parse_error(&e),
+        }
+    })?;
+
+    let problems = validation::check(&config);
+    if !problems.is_empty() {
+        return Err(BusError::Config(validation::report(
+            path, content, &problems,
+        )));
+    }
+
+    Ok(config)
+}
+
+async fn run(cli: Cli) -> Result<(), BusError> {
+    let config = load_config(&cli.config).await?;
+
+    let command = cli.command.unwrap_or(Command::Daemon);
+
+    // Only commands that change backups or databases keep a log file; the others just
+    // report to stderr and leave no trace on disk.
     let log_dir = match &config.common.log_dir {
         Some(log_file) => log_file,
         None => "logs",
     };
+    let _guard = make_logger(&cli.prefix, command.keeps_log().then_some(log_dir));
 
-    let _guard = make_logger(&cli.prefix, log_dir);
-
-    info!("Starting backup service with config: {:?}", cli.config);
-
-    let scheduler = BackupScheduler::new(config).unwrap();
-    scheduler.start().await?;
+    match command {
+        Command::Daemon => {
+            info!("Starting backup service with config: {:?}", cli.config);
 
-    Ok(())
+            let scheduler = BackupScheduler::new(config)?;
+            scheduler.start().await
+        }
+        Command::Run { alias } => commands::run_once(config, Some(&alias)).await,
+        Command::Once => commands::run_once(config, None).await,
+        Command::List { service } => commands::list(&config, service.as_deref()).await,
+        Command::Status { service } => commands::status(&config, service.as_deref()).await,
+        Command::Validate => commands::validate(config),
+        Command::Decrypt {
+            artifact,
+            output,
+            service,
+        } => commands::decrypt(&config, &artifact, output.as_deref(), service.as_deref()).await,
+        Command::Restore {
+            service,
+            backup,
+            latest: _,
+            target_db,
+        } => commands::restore(&config, &service, backup.as_deref(), target_db.as_deref()).await,
+        Command::Verify {
+            service,
+            backup,
+            latest: _,
+        } => commands::verify(&config, &service, backup.as_deref()).await,
+        Command::Prune { dry_run, service } => {
+            commands::prune(&config, service.as_deref(), dry_run).await
+        }
+    }
 }
 
 // cargo run -- --prefix cron --config ./config.toml
@@ -0,0 +1,175 @@
+use std::{collections::BTreeMap, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    common::{BackupService, Verification},
+    compression::Compression,
+    config::ServiceType,
+    encryption::Encryption,
+    error::BusError,
+    pipeline::Artifact,
+    storage::StorageBackend,
+};
+
+pub const MANIFEST_VERSION: u32 = 1;
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Sidecar describing what an artifact contains and how it was produced, stored next to
+/// it as `<artifact>.manifest.json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Manifest {
+    pub version: u32,
+    pub alias: String,
+    pub service_type: ServiceType,
+    pub artifact: String,
+    pub tool: String,
+    pub tool_version: Option<String>,
+    pub server_version: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_seconds: f64,
+    pub uncompressed_size: u64,
+    pub size: u64,
+    pub sha256: String,
+    pub compression: Compression,
+    pub encryption: Option<Encryption>,
+    pub backup_options: BTreeMap<String, String>,
+    /// The restore check of the backup, if the service has one.
+    pub verification: Option<Verification>,
+}
+
+impl Manifest {
+    pub fn new(
+        service: &dyn BackupService,
+        artifact: &Artifact,
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+    ) -> Self {
+        let name = Path::new(&artifact.path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&artifact.path);
+
+        Self {
+            version: MANIFEST_VERSION,
+            alias: service.alias().to_string(),
+            service_type: service.service_type().clone(),
+            artifact: name.to_string(),
+            tool: artifact.tool.clone(),
+            tool_version: artifact.tool_version.clone(),
+            server_version: artifact.server_version.clone(),
+            started_at,
+            finished_at,
+            duration_seconds: (finished_at - started_at).as_seconds_f64(),
+            uncompressed_size: artifact.uncompressed_size,
+            size: artifact.size,
+            sha256: artifact.sha256.clone(),
+            compression: artifact.compression,
+            encryption: artifact.encryption.clone(),
+            backup_options: service
+                .backup_options()
+                .map(|options| options.clone().into_iter().collect())
+                .unwrap_or_default(),
+            verification: None,
+        }
+    }
+
+    /// Writes the manifest next to the staged artifact and returns its path.
+    pub async fn write(&self, artifact: &Artifact) -> Result<String, BusError> {
+        let path = format!("{}{}", artifact.path, MANIFEST_SUFFIX);
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| BusError::storage(&path, std::io::Error::other(e)))?;
+
+        tokio::fs::write(&path, json)
+            .await
+            .map_err(|e| BusError::storage(&path, e))?;
+
+        Ok(path)
+    }
+}
+
+/// The fields of a stored manifest that retention and restores rely on.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub alias: String,
+    pub service_type: ServiceType,
+    pub artifact: String,
+    pub finished_at: DateTime<Utc>,
+    pub size: u64,
+    pub sha256: String,
+    /// The options the backup was taken with, e.g. its pg_dump `format`.
+    #[serde(default)]
+    pub backup_options: BTreeMap<String, String>,
+}
+
+/// An artifact Bus produced for a service, known through its manifest.
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    pub manifest_name: String,
+    pub manifest: ManifestEntry,
+}
+
+/// Lists the artifacts `backend` holds for exactly this service, oldest first. Only
+/// artifacts with a readable manifest naming this alias and type are included, so files
+/// Bus didn't write, or wrote for another service, are never considered for pruning.
+pub async fn catalog(
+    backend: &dyn StorageBackend,
+    service_type: &ServiceType,
+    alias: &str,
+) -> Result<Vec<CatalogEntry>, BusError> {
+    let prefix = format!("{}_{}_", service_type, alias);
+    let mut entries = load_catalog(backend, &prefix).await?;
+
+    // `db` shares its listing prefix with `db_replica`; the manifest settles it.
+    entries.retain(|entry| {
+        entry.manifest.alias == alias && &entry.manifest.service_type == service_type
+    });
+    Ok(entries)
+}
+
+/// Lists the artifacts of every service in `backend`, oldest first.
+pub async fn catalog_all(backend: &dyn StorageBackend) -> Result<Vec<CatalogEntry>, BusError> {
+    load_catalog(backend, "").await
+}
+
+async fn load_catalog(
+    backend: &dyn StorageBackend,
+    prefix: &str,
+) -> Result<Vec<CatalogEntry>, BusError> {
+    let mut entries = Vec::new();
+
+    for object in backend.list(prefix).await? {
+        let Some(artifact) = object.name.strip_suffix(MANIFEST_SUFFIX) else {
+            continue;
+        };
+
+        let manifest = match backend.read(&object.name).await.and_then(|contents| {
+            serde_json::from_slice::<ManifestEntry>(&contents).map_err(|e| {
+                BusError::remote(backend, format!("invalid manifest {}: {}", object.name, e))
+            })
+        }) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Skipping {} in {}: {}", object.name, backend, e);
+                continue;
+            }
+        };
+
+        // A manifest only vouches for the artifact next to it, named after its own service.
+        let service_prefix = format!("{}_{}_", manifest.service_type, manifest.alias);
+        if manifest.artifact != artifact || !artifact.starts_with(&service_prefix) {
+            continue;
+        }
+
+        entries.push(CatalogEntry {
+            manifest_name: object.name,
+            manifest,
+        });
+    }
+
+    entries.sort_by_key(|entry| entry.manifest.finished_at);
+    Ok(entries)
+}
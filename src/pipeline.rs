@@ -0,0 +1,511 @@
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Output, Stdio},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncReadExt, process::Command};
+use tokio_util::io::SyncIoBridge;
+
+use crate::{
+    compression::{self, Compression},
+    encryption::{self, Encryption, EncryptionConfig},
+    error::BusError,
+};
+
+/// A writer that has to be explicitly finished to flush trailing frames, tags or footers.
+/// Each stage finishes itself and then the stage it wraps.
+pub trait FinishWrite: Write + Send {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl FinishWrite for BufWriter<File> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()?;
+        self.get_ref().sync_all()
+    }
+}
+
+/// Size and hex SHA-256 of a finished artifact, filled in when its write chain finishes.
+type ArtifactDigest = Arc<OnceLock<(u64, String)>>;
+
+/// How long a finished or killed tool's stderr is read for; a process that left its group can
+/// hold the pipe open indefinitely.
+const STDERR_GRACE: Duration = Duration::from_secs(5);
+
+/// Bottom of the write chain: hashes and counts exactly the bytes that land in the artifact file.
+struct DigestWriter {
+    file: BufWriter<File>,
+    hasher: Sha256,
+    size: u64,
+    result: ArtifactDigest,
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl FinishWrite for DigestWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let DigestWriter {
+            file,
+            hasher,
+            size,
+            result,
+        } = *self;
+
+        Box::new(file).finish()?;
+        let _ = result.set((size, format!("{:x}", hasher.finalize())));
+        Ok(())
+    }
+}
+
+/// A finished artifact in the staging directory, as reported back to the scheduler.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub path: String,
+    pub tool: String,
+    pub tool_version: Option<String>,
+    pub server_version: Option<String>,
+    /// Bytes the dump tool produced, before compression and encryption.
+    pub uncompressed_size: u64,
+    pub size: u64,
+    pub sha256: String,
+    pub compression: Compression,
+    pub encryption: Option<Encryption>,
+}
+
+/// Streams dump output through compression and encryption straight into the final artifact,
+/// so no plaintext copy of the backup ever touches the disk.
+///
+/// Artifacts are written to `staging_dir`; the scheduler moves them on to the configured destinations.
+#[derive(Debug, Clone)]
+pub struct ArtifactPipeline {
+    staging_dir: PathBuf,
+    compression: Compression,
+    encryption: Option<Encryption>,
+}
+
+impl ArtifactPipeline {
+    pub fn new(
+        staging_dir: impl Into<PathBuf>,
+        compression: Compression,
+        encryption: Option<Encryption>,
+    ) -> Self {
+        Self {
+            staging_dir: staging_dir.into(),
+            compression,
+            encryption,
+        }
+    }
+
+    /// Staged artifact path for a raw file name like `postgres_db_<ts>.sql`,
+    /// e.g. `<staging_dir>/postgres_db_<ts>.sql.zst.age`.
+    pub fn artifact_path(&self, base: &str) -> String {
+        let mut path = self.staging_dir.join(base).display().to_string();
+        if let Some(extension) = self.compression.extension() {
+            path = format!("{}.{}", path, extension);
+        }
+        if let Some(encryption) = &self.encryption {
+            path = format!("{}.{}", path, encryption.extension());
+        }
+        path
+    }
+
+    /// Builds the write chain: compression -> encryption -> file.
+    fn sink(&self, file: File) -> Result<(Box<dyn FinishWrite>, ArtifactDigest), BusError> {
+        let result = Arc::new(OnceLock::new());
+        let mut sink: Box<dyn FinishWrite> = Box::new(DigestWriter {
+            file: BufWriter::new(file),
+            hasher: Sha256::new(),
+            size: 0,
+            result: result.clone(),
+        });
+        if let Some(encryption) = &self.encryption {
+            sink = encryption.encryptor(sink)?;
+        }
+        Ok((self.compression.encoder(sink)?, result))
+    }
+
+    /// Runs `cmd`, streaming its stdout into the artifact for `base`, and returns the staged artifact.
+    ///
+    /// The artifact is written under a `.partial` name and only renamed into place once the tool
+    /// exited successfully and every stage has been flushed.
+    pub async fn run(&self, cmd: Command, tool: &str, base: &str) -> Result<Artifact, BusError> {
+        let program = cmd.as_std().get_program().to_owned();
+        let mut artifact = self.capture(cmd, tool, base, None).await?;
+        artifact.tool_version = tool_version(&program).await;
+        Ok(artifact)
+    }
+
+    /// Like `run`, without asking the program for its version, which an arbitrary script may
+    /// not understand. With a `limit`, the program and every process it started are killed
+    /// once it is up.
+    pub async fn capture(
+        &self,
+        mut cmd: Command,
+        tool: &str,
+        base: &str,
+        limit: Option<Duration>,
+    ) -> Result<Artifact, BusError> {
+        let target = self.artifact_path(base);
+        let partial = format!("{}.partial", target);
+
+        tokio::fs::create_dir_all(&self.staging_dir)
+            .await
+            .map_err(|e| BusError::storage(&self.staging_dir, e))?;
+
+        if limit.is_some() {
+            cmd.process_group(0);
+        }
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| BusError::tool_unavailable(tool, e))?;
+        let pid = child.id();
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+
+        // Drain stderr concurrently so a chatty tool (pg_dump --verbose) can't block on a full pipe.
+        let mut stderr_task = tokio::spawn(async move {
+            let mut buffer = Vec::new();
+            let _ = stderr.read_to_end(&mut buffer).await;
+            buffer
+        });
+
+        let pipeline = self.clone();
+        let writer_path = partial.clone();
+        let writer = tokio::task::spawn_blocking(move || {
+            pipeline.write(&mut SyncIoBridge::new(stdout), &writer_path)
+        });
+
+        let finished = async {
+            let written = writer
+                .await
+                .map_err(|e| BusError::Compression(e.to_string()))
+                .and_then(|result| result);
+
+            if written.is_err() {
+                let _ = child.start_kill();
+            }
+
+            let status = child
+                .wait()
+                .await
+                .map_err(|e| BusError::tool_unavailable(tool, e));
+            (status, written)
+        };
+        let (status, written) = match limit {
+            None => finished.await,
+            Some(limit) => match tokio::time::timeout(limit, finished).await {
+                Ok(finished) => finished,
+                Err(_) => {
+                    kill_process_group(pid);
+                    (
+                        Err(BusError::tool_timed_out(tool, limit)),
+                        Ok(Default::default()),
+                    )
+                }
+            },
+        };
+        let stderr = match tokio::time::timeout(STDERR_GRACE, &mut stderr_task).await {
+            Ok(stderr) => stderr.unwrap_or_default(),
+            Err(_) => {
+                stderr_task.abort();
+                Vec::new()
+            }
+        };
+
+        let result = match (status, written) {
+            (Ok(status), Ok(written)) if status.success() => Ok(written),
+            (Ok(status), Ok(_)) => Err(BusError::tool_failed(
+                tool,
+                &Output {
+                    status,
+                    stdout: Vec::new(),
+                    stderr,
+                },
+            )),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+
+        let written = match result {
+            Ok(written) => written,
+            Err(e) => {
+                // Never leave a truncated artifact behind.
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+
+        self.finish(&partial, target, tool, written).await
+    }
+
+    /// Streams the local file `source`, such as one a backup command wrote, into the artifact
+    /// for `base`.
+    pub async fn ingest(
+        &self,
+        source: &Path,
+        tool: &str,
+        base: &str,
+    ) -> Result<Artifact, BusError> {
+        let target = self.artifact_path(base);
+        let partial = format!("{}.partial", target);
+
+        tokio::fs::create_dir_all(&self.staging_dir)
+            .await
+            .map_err(|e| BusError::storage(&self.staging_dir, e))?;
+
+        let pipeline = self.clone();
+        let source = source.to_path_buf();
+        let writer_path = partial.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let mut file = File::open(&source).map_err(|e| BusError::storage(&source, e))?;
+            pipeline.write(&mut file, &writer_path)
+        })
+        .await
+        .map_err(|e| BusError::Compression(e.to_string()))
+        .and_then(|result| result);
+
+        match written {
+            Ok(written) => self.finish(&partial, target, tool, written).await,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Copies `reader` through the write chain into the file `path`; returns the bytes read,
+    /// the bytes written and the SHA-256 of the written file.
+    fn write(&self, reader: &mut impl Read, path: &str) -> Result<(u64, u64, String), BusError> {
+        let file = File::create(path).map_err(|e| BusError::storage(path, e))?;
+        let (mut encoder, digest) = self.sink(file)?;
+
+        let uncompressed_size =
+            io::copy(reader, &mut encoder).map_err(|e| BusError::storage(path, e))?;
+        encoder.finish().map_err(|e| BusError::storage(path, e))?;
+
+        let (size, sha256) = digest.get().cloned().unwrap_or_default();
+        Ok((uncompressed_size, size, sha256))
+    }
+
+    /// Moves a completely written artifact into place.
+    async fn finish(
+        &self,
+        partial: &str,
+        target: String,
+        tool: &str,
+        (uncompressed_size, size, sha256): (u64, u64, String),
+    ) -> Result<Artifact, BusError> {
+        tokio::fs::rename(partial, &target)
+            .await
+            .map_err(|e| BusError::storage(&target, e))?;
+
+        Ok(Artifact {
+            path: target,
+            tool: tool.to_string(),
+            tool_version: None,
+            server_version: None,
+            uncompressed_size,
+            size,
+            sha256,
+            compression: self.compression,
+            encryption: self.encryption.clone(),
+        })
+    }
+}
+
+/// Kills the process group led by `pid`, i.e. a program started with `process_group(0)` and
+/// whatever it started in turn, such as the tools a dump script runs.
+pub fn kill_process_group(pid: Option<u32>) {
+    if let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+        // SAFETY: kill(2) takes no pointers; a negative pid addresses the whole group.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+}
+
+/// First line of `<program> --version`, e.g. `pg_dump (PostgreSQL) 15.8`.
+pub async fn tool_version(program: &OsStr) -> Option<String> {
+    let output = Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next()?.trim();
+    (output.status.success() && !version.is_empty()).then(|| version.to_string())
+}
+
+impl Display for ArtifactPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} compression", self.compression.codec)?;
+        if let Some(encryption) = &self.encryption {
+            write!(f, ", {} encryption", encryption)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the original dump bytes of `artifact` to `output`, or to stdout when `None`.
+pub async fn decode_artifact(
+    artifact: &Path,
+    output: Option<&Path>,
+    keys: Option<&EncryptionConfig>,
+) -> Result<u64, BusError> {
+    let artifact = artifact.to_path_buf();
+    let output = output.map(Path::to_path_buf);
+    let keys = keys.cloned();
+
+    tokio::task::spawn_blocking(move || {
+        let mut reader = open_artifact(&artifact, keys.as_ref())?;
+        let copied = match &output {
+            Some(path) => {
+                let mut file = File::create(path).map_err(|e| BusError::storage(path, e))?;
+                io::copy(&mut reader, &mut file).map_err(|e| BusError::storage(path, e))?
+            }
+            None => io::copy(&mut reader, &mut io::stdout().lock())
+                .map_err(|e| BusError::storage(&artifact, e))?,
+        };
+
+        Ok(copied)
+    })
+    .await
+    .map_err(|e| BusError::Encryption(e.to_string()))?
+}
+
+/// Streams the original dump bytes of `artifact` into the stdin of `cmd`, e.g. `psql`, and
+/// waits for it to exit successfully.
+pub async fn feed(
+    mut cmd: Command,
+    tool: &str,
+    artifact: &Path,
+    keys: Option<&EncryptionConfig>,
+) -> Result<u64, BusError> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| BusError::tool_unavailable(tool, e))?;
+
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    let stderr_task = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        let _ = stderr.read_to_end(&mut buffer).await;
+        buffer
+    });
+
+    let artifact = artifact.to_path_buf();
+    let keys = keys.cloned();
+    let written = tokio::task::spawn_blocking(move || -> Result<_, BusError> {
+        let mut reader = open_artifact(&artifact, keys.as_ref())?;
+        // Dropping the bridge closes stdin, which ends the tool's input.
+        let mut writer = SyncIoBridge::new(stdin);
+        io::copy(&mut reader, &mut writer).map_err(|e| BusError::storage(&artifact, e))
+    })
+    .await
+    .map_err(|e| BusError::Encryption(e.to_string()))
+    .and_then(|result| result);
+
+    if written.is_err() {
+        let _ = child.start_kill();
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| BusError::tool_unavailable(tool, e));
+    let stderr = stderr_task.await.unwrap_or_default();
+    let failed = |status| {
+        BusError::tool_failed(
+            tool,
+            &Output {
+                status,
+                stdout: Vec::new(),
+                stderr,
+            },
+        )
+    };
+
+    match (status, written) {
+        (Ok(status), Ok(copied)) if status.success() => Ok(copied),
+        (Ok(status), Ok(_)) => Err(failed(status)),
+        // A tool that gives up stops reading; its own error says why.
+        (Ok(status), Err(BusError::Storage { source, .. }))
+            if source.kind() == io::ErrorKind::BrokenPipe =>
+        {
+            Err(failed(status))
+        }
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
+/// Hex SHA-256 of a stored artifact, to compare with its manifest.
+pub async fn sha256_file(path: &Path) -> Result<String, BusError> {
+    let artifact = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&artifact).map_err(|e| BusError::storage(&artifact, e))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).map_err(|e| BusError::storage(&artifact, e))?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(|e| BusError::storage(path, io::Error::other(e)))?
+}
+
+/// Opens an artifact for reading its original dump bytes, undoing encryption and compression
+/// as named by its extensions. Blocking; call from `spawn_blocking`.
+pub fn open_artifact(
+    path: &Path,
+    keys: Option<&EncryptionConfig>,
+) -> Result<Box<dyn Read + Send>, BusError> {
+    let file = File::open(path).map_err(|e| BusError::storage(path, e))?;
+    let mut reader: Box<dyn Read + Send> = Box::new(BufReader::new(file));
+
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let mut extensions = name.rsplit('.').peekable();
+
+    if let Some(&extension) = extensions.peek()
+        && matches!(extension, "age" | "enc")
+    {
+        reader = encryption::decryptor(extension, keys, reader)?;
+        extensions.next();
+    }
+
+    if let Some(extension) = extensions.next() {
+        reader = compression::decoder(extension, reader)?;
+    }
+
+    Ok(reader)
+}
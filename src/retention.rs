@@ -0,0 +1,311 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::{
+    config::{CommonConfig, QuotaConfig, RetentionConfig, ServiceType},
+    error::BusError,
+    manifest::{CatalogEntry, catalog, catalog_all},
+    storage::Destination,
+};
+
+/// How long a destination keeps the artifacts of a service.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetentionPolicy {
+    /// Removes artifacts that finished more than this many days ago.
+    MaxAge(i64),
+    /// Grandfather-father-son: keeps the newest artifact of each of the most recent periods.
+    Gfs(RetentionConfig),
+}
+
+/// GFS periods, each with the `strftime` key identifying a period in UTC.
+const PERIODS: [(&str, &str); 5] = [
+    ("hourly", "%Y-%m-%d %H"),
+    ("daily", "%Y-%m-%d"),
+    ("weekly", "%G-W%V"),
+    ("monthly", "%Y-%m"),
+    ("yearly", "%Y"),
+];
+
+impl RetentionPolicy {
+    /// A service's `retention` replaces `common.retention`; without either, artifacts are
+    /// kept for `common.retention_days` (default 7).
+    pub fn resolve(
+        common: &CommonConfig,
+        service: Option<&RetentionConfig>,
+    ) -> Result<Self, BusError> {
+        match service.or(common.retention.as_ref()) {
+            Some(config) => Self::from_config(config),
+            None => Ok(Self::MaxAge(common.retention_days.unwrap_or(7))),
+        }
+    }
+
+    pub fn from_config(config: &RetentionConfig) -> Result<Self, BusError> {
+        if config.counts().iter().all(|&count| count == 0) {
+            return Err(BusError::Config(
+                "'retention' must keep at least one backup".into(),
+            ));
+        }
+
+        Ok(Self::Gfs(config.clone()))
+    }
+
+    /// Returns the entries this policy no longer keeps; `entries` are sorted oldest first.
+    pub fn expired<'a>(
+        &self,
+        entries: &'a [CatalogEntry],
+        now: DateTime<Utc>,
+    ) -> Vec<&'a CatalogEntry> {
+        match self {
+            RetentionPolicy::MaxAge(days) => {
+                let cutoff = now - chrono::Duration::days(*days);
+                entries
+                    .iter()
+                    .filter(|entry| entry.manifest.finished_at < cutoff)
+                    .collect()
+            }
+            RetentionPolicy::Gfs(config) => {
+                let kept = config.kept(entries);
+                entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| !kept.contains(index))
+                    .map(|(_, entry)| entry)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A cap on the bytes stored in a destination, by one service or all of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub max_total_bytes: u64,
+    pub min_keep: usize,
+}
+
+impl Quota {
+    pub fn from_config(config: Option<&QuotaConfig>) -> Result<Option<Self>, BusError> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+
+        if config.min_keep == 0 {
+            return Err(BusError::Config(
+                "'quota.min_keep' must be at least 1".into(),
+            ));
+        }
+
+        Ok(Some(Self {
+            max_total_bytes: config.max_total_bytes,
+            min_keep: config.min_keep as usize,
+        }))
+    }
+
+    /// Returns the oldest entries to remove until the rest fit, never including the newest
+    /// `min_keep` of any service; `entries` are sorted oldest first.
+    pub fn over_quota<'a>(&self, entries: &'a [CatalogEntry]) -> Vec<&'a CatalogEntry> {
+        let mut newest = HashMap::new();
+        let protected: HashSet<usize> = (0..entries.len())
+            .rev()
+            .filter(|&index| {
+                let manifest = &entries[index].manifest;
+                let seen = newest
+                    .entry((manifest.service_type.to_string(), manifest.alias.as_str()))
+                    .or_insert(0);
+                *seen += 1;
+                *seen <= self.min_keep
+            })
+            .collect();
+
+        let mut total: u64 = entries.iter().map(|entry| entry.manifest.size).sum();
+        let mut removed = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if total <= self.max_total_bytes {
+                break;
+            }
+            if !protected.contains(&index) {
+                total -= entry.manifest.size;
+                removed.push(entry);
+            }
+        }
+
+        removed
+    }
+
+    fn removals(
+        &self,
+        destination: &Destination,
+        entries: &[CatalogEntry],
+        scope: &str,
+    ) -> Vec<Removal> {
+        let removed = self.over_quota(entries);
+
+        let total: u64 = entries.iter().map(|entry| entry.manifest.size).sum::<u64>()
+            - removed.iter().map(|entry| entry.manifest.size).sum::<u64>();
+        if total > self.max_total_bytes {
+            warn!(
+                "Backups of {} in {} use {} bytes, over the quota of {} bytes; the newest {} of each service are always kept",
+                scope, destination.backend, total, self.max_total_bytes, self.min_keep
+            );
+        }
+
+        removed
+            .into_iter()
+            .map(|entry| Removal {
+                entry: entry.clone(),
+                reason: format!("{} over {} bytes", scope, self.max_total_bytes),
+            })
+            .collect()
+    }
+}
+
+/// An artifact cleanup removes, and why.
+#[derive(Debug, Clone)]
+pub struct Removal {
+    pub entry: CatalogEntry,
+    pub reason: String,
+}
+
+fn list_error(destination: &Destination, error: BusError) -> BusError {
+    BusError::Retention(format!("Failed to list {}: {}", destination.backend, error))
+}
+
+/// Selects what cleanup removes for one service in `destination`, without deleting anything:
+/// artifacts its retention policy no longer keeps, then the oldest beyond `quota`. Only
+/// artifacts in the service's catalog are ever selected.
+pub async fn plan_cleanup(
+    destination: &Destination,
+    service_type: &ServiceType,
+    alias: &str,
+    quota: Option<&Quota>,
+) -> Result<Vec<Removal>, BusError> {
+    let entries = catalog(destination.backend.as_ref(), service_type, alias)
+        .await
+        .map_err(|e| list_error(destination, e))?;
+
+    let mut removals: Vec<Removal> = destination
+        .retention
+        .expired(&entries, Utc::now())
+        .into_iter()
+        .map(|entry| Removal {
+            entry: entry.clone(),
+            reason: destination.retention.to_string(),
+        })
+        .collect();
+
+    if let Some(quota) = quota {
+        let remaining = without(&entries, &removals);
+        let scope = format!("'{}'", alias);
+        removals.extend(quota.removals(destination, &remaining, &scope));
+    }
+
+    Ok(removals)
+}
+
+/// Selects the oldest artifacts of any service beyond `quota` in `destination`, once the
+/// `planned` removals are gone.
+pub async fn plan_total_quota(
+    destination: &Destination,
+    quota: &Quota,
+    planned: &[Removal],
+) -> Result<Vec<Removal>, BusError> {
+    let entries = catalog_all(destination.backend.as_ref())
+        .await
+        .map_err(|e| list_error(destination, e))?;
+
+    let remaining = without(&entries, planned);
+    Ok(quota.removals(destination, &remaining, "all services"))
+}
+
+fn without(entries: &[CatalogEntry], removals: &[Removal]) -> Vec<CatalogEntry> {
+    entries
+        .iter()
+        .filter(|entry| {
+            !removals
+                .iter()
+                .any(|removal| removal.entry.manifest_name == entry.manifest_name)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Deletes the artifact, then its manifest, so a failed delete is retried by the next cleanup.
+pub async fn remove_entry(destination: &Destination, entry: &CatalogEntry) -> Result<(), BusError> {
+    let artifact = &entry.manifest.artifact;
+    if let Err(e) = destination.backend.delete(artifact).await {
+        // An artifact that is already gone must not pin its manifest forever.
+        if destination.backend.stat(artifact).await.is_ok() {
+            return Err(e);
+        }
+    }
+
+    destination.backend.delete(&entry.manifest_name).await
+}
+
+impl RetentionConfig {
+    /// `keep_last` followed by the counts of `PERIODS`.
+    fn counts(&self) -> [u32; 6] {
+        [
+            self.keep_last,
+            self.keep_hourly,
+            self.keep_daily,
+            self.keep_weekly,
+            self.keep_monthly,
+            self.keep_yearly,
+        ]
+    }
+
+    /// Indices of the entries kept by any rule.
+    fn kept(&self, entries: &[CatalogEntry]) -> HashSet<usize> {
+        let counts = self.counts();
+        let newest_first = (0..entries.len()).rev();
+
+        let mut kept: HashSet<usize> = newest_first.clone().take(counts[0] as usize).collect();
+
+        for ((_, format), &count) in PERIODS.iter().zip(&counts[1..]) {
+            let mut periods = HashSet::new();
+            for index in newest_first.clone() {
+                if periods.len() == count as usize {
+                    break;
+                }
+                let period = entries[index]
+                    .manifest
+                    .finished_at
+                    .format(format)
+                    .to_string();
+                // The first artifact seen in a period is its newest.
+                if periods.insert(period) {
+                    kept.insert(index);
+                }
+            }
+        }
+
+        kept
+    }
+}
+
+impl Display for RetentionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetentionPolicy::MaxAge(days) => write!(f, "older than {} days", days),
+            RetentionPolicy::Gfs(config) => {
+                let counts = config.counts();
+                let mut rules = Vec::new();
+                if counts[0] > 0 {
+                    rules.push(format!("last {}", counts[0]));
+                }
+                for ((name, _), &count) in PERIODS.iter().zip(&counts[1..]) {
+                    if count > 0 {
+                        rules.push(format!("{} {}", count, name));
+                    }
+                }
+                write!(f, "beyond {}", rules.join(", "))
+            }
+        }
+    }
+}
@@ -1,28 +1,103 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use chrono::{DateTime, Utc};
-use tokio::time;
 use tracing::{error, info, warn};
 
 use crate::{
-    common::BackupService,
+    common::{BackupService, RestoreOptions, Verification},
     config::{CommonConfig, Config},
-    service::ServiceFactory,
+    encryption::EncryptionConfig,
+    error::BusError,
+    manifest::{MANIFEST_SUFFIX, Manifest, catalog},
+    pipeline::Artifact,
+    retention::{Quota, Removal, plan_cleanup, plan_total_quota, remove_entry},
+    scheduler::{retry::RetryPolicy, schedule::Schedule},
+    service::{STAGING_DIR, ServiceFactory},
+    storage::Destination,
 };
 
+pub mod retry;
+pub mod schedule;
+
 pub struct BackupScheduler {
-    services: Vec<Arc<dyn BackupService>>,
+    services: Vec<ScheduledService>,
     common_config: CommonConfig,
 }
 
+#[derive(Clone)]
+struct ScheduledService {
+    service: Arc<dyn BackupService>,
+    schedule: Schedule,
+    retry: RetryPolicy,
+    destinations: Arc<Vec<Destination>>,
+    quota: Option<Quota>,
+    /// `common.quota`, shared by all services in a destination.
+    total_quota: Option<Quota>,
+    /// Keys to decrypt fresh artifacts with for verification.
+    keys: Option<EncryptionConfig>,
+    /// Backups that haven't reached every destination yet, kept under the service's retention.
+    staging: Arc<Destination>,
+    staging_dir: PathBuf,
+}
+
 impl BackupScheduler {
-    pub fn new(config: Config) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn new(config: Config) -> Result<Self, BusError> {
         let mut services = Vec::new();
 
+        let now = Utc::now();
+        let total_quota = Quota::from_config(config.common.quota.as_ref())?;
+        let staging_dir = Path::new(&config.common.backup_dir).join(STAGING_DIR);
+
         for service_config in config.services {
-            let service =
-                ServiceFactory::create_service(service_config, config.common.backup_dir.clone())?;
-            services.push(Arc::from(service));
+            let invalid_schedule = |e: BusError| {
+                BusError::Config(format!(
+                    "Invalid schedule for '{}': {}",
+                    service_config.alias, e
+                ))
+            };
+            let schedule =
+                Schedule::from_config(&service_config.schedule, now).map_err(invalid_schedule)?;
+            let retry = RetryPolicy::from_config(service_config.schedule.retry.as_ref())
+                .map_err(invalid_schedule)?;
+            let destinations = Destination::resolve(
+                &config.common,
+                service_config.destinations.as_ref(),
+                service_config.retention.as_ref(),
+            )
+            .map_err(|e| {
+                BusError::Config(format!(
+                    "Invalid destinations for '{}': {}",
+                    service_config.alias, e
+                ))
+            })?;
+            let quota = Quota::from_config(service_config.quota.as_ref()).map_err(|e| {
+                BusError::Config(format!(
+                    "Invalid quota for '{}': {}",
+                    service_config.alias, e
+                ))
+            })?;
+            let keys = service_config
+                .encryption
+                .clone()
+                .or_else(|| config.common.encryption.clone());
+            let staging = Destination::staging(&config.common, service_config.retention.as_ref())?;
+            let service = ServiceFactory::create_service(service_config, &config.common)?;
+
+            services.push(ScheduledService {
+                service: Arc::from(service),
+                schedule,
+                retry,
+                destinations: Arc::new(destinations),
+                quota,
+                total_quota,
+                keys,
+                staging: Arc::new(staging),
+                staging_dir: staging_dir.clone(),
+            });
         }
 
         Ok(Self {
@@ -31,21 +106,20 @@ impl BackupScheduler {
         })
     }
 
-    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn start(&self) -> Result<(), BusError> {
         info!(
             "Starting backup scheduler with {} services",
             self.services.len()
         );
 
-        tokio::fs::create_dir_all(&self.common_config.backup_dir).await?;
+        tokio::fs::create_dir_all(&self.common_config.backup_dir)
+            .await
+            .map_err(|e| BusError::storage(&self.common_config.backup_dir, e))?;
 
         let mut handles = Vec::new();
 
-        for service in &self.services {
-            let service_clone = Arc::clone(service);
-            let common_config = self.common_config.clone();
-
-            let handle = tokio::spawn(Self::run_service_scheduler(service_clone, common_config));
+        for scheduled in &self.services {
+            let handle = tokio::spawn(Self::run_service_scheduler(scheduled.clone()));
 
             handles.push(handle);
         }
@@ -64,86 +138,444 @@ impl BackupScheduler {
         Ok(())
     }
 
-    async fn run_service_scheduler(
-        service: Arc<dyn BackupService>,
-        common_config: CommonConfig,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut interval =
-            time::interval(Duration::from_secs(service.get_schedule().interval_seconds));
+    /// Backs up the service `alias`, or every service one after another, right away, without
+    /// waiting for their schedules. Fails if any backup didn't reach all of its destinations.
+    pub async fn run_once(&self, alias: Option<&str>) -> Result<(), BusError> {
+        let selected: Vec<&ScheduledService> = self
+            .services
+            .iter()
+            .filter(|scheduled| alias.is_none_or(|alias| scheduled.service.alias() == alias))
+            .collect();
+
+        if let Some(alias) = alias
+            && selected.is_empty()
+        {
+            return Err(BusError::Config(format!("Unknown service '{}'", alias)));
+        }
+
+        tokio::fs::create_dir_all(&self.common_config.backup_dir)
+            .await
+            .map_err(|e| BusError::storage(&self.common_config.backup_dir, e))?;
+
+        let mut failed = Vec::new();
+        for scheduled in selected {
+            if !Self::run_cycle(scheduled).await {
+                failed.push(format!("'{}'", scheduled.service.alias()));
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(BusError::Backup(failed));
+        }
+
+        Ok(())
+    }
+
+    async fn run_service_scheduler(scheduled: ScheduledService) -> Result<(), BusError> {
+        let service = &scheduled.service;
 
         info!(
-            "Started scheduler for service '{}' with interval {} seconds",
+            "Started scheduler for service '{}' with {}",
             service.alias(),
-            service.get_schedule().interval_seconds
+            service.get_schedule()
         );
 
+        let mut next_run = scheduled.schedule.first_run(Utc::now())?;
+
         loop {
-            interval.tick().await;
+            info!(
+                "Next backup for service '{}' at {}",
+                service.alias(),
+                next_run
+            );
 
-            let timestamp = Utc::now().format("%Y-%m-%d_%H:%M:%S.%f").to_string();
+            let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
 
-            info!("Starting backup for service '{}'", service.alias());
+            Self::run_cycle(&scheduled).await;
 
-            match service.backup(&timestamp).await {
-                Ok(backup_file) => {
-                    info!(
-                        "Backup completed for '{}': {}",
-                        service.alias(),
-                        backup_file
-                    );
-                }
-                Err(e) => {
-                    error!("Backup failed for '{}': {}", service.alias(), e);
-                }
+            // Skip any slots missed while the backup was running instead of bursting.
+            next_run = scheduled.schedule.next_run(Utc::now().max(next_run))?;
+        }
+    }
+
+    /// Backs up, delivers and cleans up once; returns whether every destination got the backup
+    /// and it didn't fail verification.
+    async fn run_cycle(scheduled: &ScheduledService) -> bool {
+        let ScheduledService {
+            service,
+            retry,
+            destinations,
+            quota,
+            total_quota,
+            keys,
+            staging,
+            staging_dir,
+            ..
+        } = scheduled;
+        let destinations: Vec<&Destination> = destinations.iter().collect();
+
+        Self::redeliver(service.as_ref(), &destinations, staging, staging_dir, retry).await;
+
+        let delivered = match Self::run_backup_with_retry(service.as_ref(), retry, keys).await {
+            // A backup that failed verification is still stored; the check may be what's wrong.
+            Some((staged, verified)) => {
+                Self::deliver(service.as_ref(), &destinations, &staged, retry).await && verified
             }
+            None => false,
+        };
 
-            if let Err(e) = Self::cleanup_old_backups(&common_config, service.alias()).await {
+        // Backups still waiting in staging take up `backup_dir` too, so both quotas cover them.
+        for destination in destinations.iter().copied().chain([staging.as_ref()]) {
+            let cleanup = Self::cleanup_old_backups(
+                service.as_ref(),
+                destination,
+                quota.as_ref(),
+                total_quota.as_ref(),
+            );
+            if let Err(e) = cleanup.await {
                 warn!(
-                    "Failed to cleanup old backups for '{}': {}",
+                    error_kind = e.kind(),
+                    "Failed to cleanup old backups for '{}' in {}: {}",
                     service.alias(),
+                    destination.backend,
                     e
                 );
             }
         }
+
+        delivered
     }
 
-    async fn cleanup_old_backups(
-        common_config: &CommonConfig,
-        service_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let retention_days = common_config.retention_days.unwrap_or(7);
-        let cutoff_date = Utc::now() - chrono::Duration::days(retention_days);
+    /// Runs and verifies the backup and writes its manifest, returning the staged files to
+    /// deliver, the artifact followed by its manifest, and whether verification didn't fail.
+    async fn run_backup_with_retry(
+        service: &dyn BackupService,
+        retry: &RetryPolicy,
+        keys: &Option<EncryptionConfig>,
+    ) -> Option<(Vec<String>, bool)> {
+        let (artifact, started_at) = Self::with_retry(
+            service,
+            retry,
+            "backup",
+            |e| service.is_retryable(e),
+            || {
+                let started_at = Utc::now();
+                let timestamp = started_at.format("%Y-%m-%d_%H:%M:%S.%f").to_string();
+                async move {
+                    let artifact = service.backup(&timestamp).await?;
+                    Ok::<(Artifact, DateTime<Utc>), BusError>((artifact, started_at))
+                }
+            },
+        )
+        .await?;
 
         info!(
-            "Cleaning up backups older than {} days for service '{}'",
-            retention_days, service_name
+            "Backup completed for '{}': {} ({} bytes, sha256 {})",
+            service.alias(),
+            artifact.path,
+            artifact.size,
+            artifact.sha256
         );
 
-        let mut entries = tokio::fs::read_dir(&common_config.backup_dir).await?;
-
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            let file_name = path
-                .file_name()
-                .and_then(|name| name.to_str())
-                .unwrap_or_default();
-
-            if file_name.contains(&format!("{}_{}", service_name.replace("-", "_"), "")) {
-                if let Ok(metadata) = entry.metadata().await {
-                    if let Ok(created) = metadata.created() {
-                        let created_datetime: DateTime<Utc> = created.into();
-
-                        if created_datetime < cutoff_date {
-                            info!("Removing old backup for '{}': {:?}", service_name, path);
-                            if let Err(e) = tokio::fs::remove_file(&path).await {
-                                warn!("Failed to remove old backup {:?}: {}", path, e);
-                            }
-                        }
+        let mut manifest = Manifest::new(service, &artifact, started_at, Utc::now());
+        manifest.verification = Self::verify(service, &artifact, keys).await;
+        let verified = manifest
+            .verification
+            .as_ref()
+            .is_none_or(|verification| verification.passed);
+
+        let manifest_path = Self::with_retry(
+            service,
+            retry,
+            "manifest write",
+            BusError::is_retryable,
+            || manifest.write(&artifact),
+        )
+        .await;
+
+        match manifest_path {
+            Some(manifest_path) => Some((vec![artifact.path, manifest_path], verified)),
+            None => {
+                // Without a manifest retention would never see the artifact, so it is not stored.
+                if let Err(e) = tokio::fs::remove_file(&artifact.path).await {
+                    warn!("Failed to remove staged file {}: {}", artifact.path, e);
+                }
+                None
+            }
+        }
+    }
+
+    async fn verify(
+        service: &dyn BackupService,
+        artifact: &Artifact,
+        keys: &Option<EncryptionConfig>,
+    ) -> Option<Verification> {
+        let options = RestoreOptions {
+            keys: keys.clone(),
+            backup_options: service
+                .backup_options()
+                .map(|options| options.clone().into_iter().collect())
+                .unwrap_or_default(),
+            target_db: None,
+        };
+        let verification = service.verify(Path::new(&artifact.path), &options).await?;
+
+        match &verification.error {
+            None => info!(
+                "Verified backup of '{}' in scratch database '{}': {} tables, row counts {:?}",
+                service.alias(),
+                verification.database,
+                verification.tables.unwrap_or_default(),
+                verification.row_counts
+            ),
+            Some(e) => error!(
+                error_kind = "verification",
+                "Verification of backup of '{}' in scratch database '{}' failed: {}",
+                service.alias(),
+                verification.database,
+                e
+            ),
+        }
+
+        Some(verification)
+    }
+
+    /// Stores the staged files in every destination and removes them from staging
+    /// once all of them have every file; returns whether they did.
+    pub(crate) async fn deliver(
+        service: &dyn BackupService,
+        destinations: &[&Destination],
+        staged: &[String],
+        retry: &RetryPolicy,
+    ) -> bool {
+        let mut delivered = true;
+
+        for destination in destinations {
+            for file in staged {
+                let source = Path::new(file);
+                let name = source
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or(file);
+
+                let stored = Self::with_retry(
+                    service,
+                    retry,
+                    &format!("upload to {}", destination.backend),
+                    BusError::is_retryable,
+                    || Self::store(destination, source, name),
+                )
+                .await;
+
+                match stored {
+                    Some(()) => info!(
+                        "Stored backup for '{}' in {}: {}",
+                        service.alias(),
+                        destination.backend,
+                        name
+                    ),
+                    None => {
+                        delivered = false;
+                        // Without the artifact the manifest would describe nothing.
+                        break;
                     }
                 }
             }
         }
 
+        for file in staged {
+            if !delivered {
+                warn!(
+                    "Keeping staged file for '{}' at {} because not every destination has it",
+                    service.alias(),
+                    file
+                );
+            } else if let Err(e) = tokio::fs::remove_file(file).await {
+                warn!("Failed to remove staged file {}: {}", file, e);
+            }
+        }
+
+        delivered
+    }
+
+    /// Delivers the backups earlier cycles left in `staging` to the destinations that are
+    /// still missing them, and removes them from staging once every destination has them.
+    pub(crate) async fn redeliver(
+        service: &dyn BackupService,
+        destinations: &[&Destination],
+        staging: &Destination,
+        staging_dir: &Path,
+        retry: &RetryPolicy,
+    ) {
+        let entries = match catalog(
+            staging.backend.as_ref(),
+            service.service_type(),
+            service.alias(),
+        )
+        .await
+        {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(
+                    error_kind = e.kind(),
+                    "Failed to list staged backups for '{}': {}",
+                    service.alias(),
+                    e
+                );
+                return;
+            }
+        };
+
+        for entry in entries {
+            let mut missing = Vec::new();
+            for destination in destinations {
+                // The manifest is stored last, so a destination that has it has the backup.
+                if destination
+                    .backend
+                    .stat(&entry.manifest_name)
+                    .await
+                    .is_err()
+                {
+                    missing.push(*destination);
+                }
+            }
+
+            if !missing.is_empty() {
+                info!(
+                    "Delivering staged backup of '{}' to {} destination(s) missing it: {}",
+                    service.alias(),
+                    missing.len(),
+                    entry.manifest.artifact
+                );
+            }
+
+            let artifact = staging_dir.join(&entry.manifest.artifact);
+            let staged = [
+                artifact.display().to_string(),
+                format!("{}{}", artifact.display(), MANIFEST_SUFFIX),
+            ];
+            Self::deliver(service, &missing, &staged, retry).await;
+        }
+    }
+
+    /// Puts the artifact and checks the stored copy is complete.
+    async fn store(destination: &Destination, source: &Path, name: &str) -> Result<(), BusError> {
+        let expected = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| BusError::storage(source, e))?
+            .len();
+
+        destination.backend.put(source, name).await?;
+
+        let stored = destination.backend.stat(name).await?;
+        if stored.size != expected {
+            return Err(BusError::remote(
+                &destination.backend,
+                format!(
+                    "stored {} is {} bytes, expected {}",
+                    name, stored.size, expected
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Runs `operation` until it succeeds, fails with a non-retryable error or runs out of attempts.
+    async fn with_retry<T, F, Fut>(
+        service: &dyn BackupService,
+        retry: &RetryPolicy,
+        action: &str,
+        is_retryable: impl Fn(&BusError) -> bool,
+        mut operation: F,
+    ) -> Option<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BusError>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            info!(
+                "Starting {} for service '{}' (attempt {}/{})",
+                action,
+                service.alias(),
+                attempt,
+                retry.max_attempts
+            );
+
+            match operation().await {
+                Ok(value) => return Some(value),
+                Err(e) if attempt < retry.max_attempts && is_retryable(&e) => {
+                    let delay = retry.delay(attempt);
+                    warn!(
+                        error_kind = e.kind(),
+                        "Attempt {}/{} of {} failed for '{}': {}; retrying in {:.1}s",
+                        attempt,
+                        retry.max_attempts,
+                        action,
+                        service.alias(),
+                        e,
+                        delay.as_secs_f64()
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!(
+                        error_kind = e.kind(),
+                        "Giving up on {} for '{}' after {} attempt(s): {}",
+                        action,
+                        service.alias(),
+                        attempt,
+                        e
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Deletes the artifacts of `service` retention and `quota` select in `destination`, then
+    /// the oldest of any service beyond `total_quota`.
+    pub(crate) async fn cleanup_old_backups(
+        service: &dyn BackupService,
+        destination: &Destination,
+        quota: Option<&Quota>,
+        total_quota: Option<&Quota>,
+    ) -> Result<(), BusError> {
+        info!(
+            "Cleaning up backups {} for service '{}' in {}",
+            destination.retention,
+            service.alias(),
+            destination.backend
+        );
+
+        let mut removals =
+            plan_cleanup(destination, service.service_type(), service.alias(), quota).await?;
+        if let Some(quota) = total_quota {
+            removals.extend(plan_total_quota(destination, quota, &removals).await?);
+        }
+
+        for Removal { entry, reason } in removals {
+            info!(
+                "Removing backup of '{}' from {} ({}): {} ({} bytes)",
+                entry.manifest.alias,
+                destination.backend,
+                reason,
+                entry.manifest.artifact,
+                entry.manifest.size
+            );
+            if let Err(e) = remove_entry(destination, &entry).await {
+                warn!(
+                    "Failed to remove old backup {}: {}",
+                    entry.manifest.artifact, e
+                );
+            }
+        }
+
         Ok(())
     }
 }
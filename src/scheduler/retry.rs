@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use crate::{config::RetryConfig, error::BusError};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    initial_delay: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    /// A single attempt, used when a service has no `[services.schedule.retry]` section.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay: Duration::ZERO,
+            multiplier: 1.0,
+            max_delay: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+
+    pub fn from_config(config: Option<&RetryConfig>) -> Result<Self, BusError> {
+        let Some(config) = config else {
+            return Ok(Self::none());
+        };
+
+        if config.max_attempts == 0 {
+            return Err(BusError::Config(
+                "'retry.max_attempts' must be at least 1".into(),
+            ));
+        }
+        if config.multiplier < 1.0 {
+            return Err(BusError::Config(
+                "'retry.multiplier' must be at least 1.0".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&config.jitter) {
+            return Err(BusError::Config(
+                "'retry.jitter' must be between 0.0 and 1.0".into(),
+            ));
+        }
+
+        Ok(Self {
+            max_attempts: config.max_attempts,
+            initial_delay: Duration::from_secs(config.initial_delay_seconds),
+            multiplier: config.multiplier,
+            max_delay: Duration::from_secs(config.max_delay_seconds),
+            jitter: config.jitter,
+        })
+    }
+
+    /// Delay before the attempt following failed attempt number `attempt` (1-based), without jitter.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt).as_secs_f64();
+        if self.jitter == 0.0 || base == 0.0 {
+            return Duration::from_secs_f64(base);
+        }
+
+        let spread = rand::random_range(-self.jitter..=self.jitter);
+        Duration::from_secs_f64((base * (1.0 + spread)).max(0.0))
+    }
+}
@@ -0,0 +1,209 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+
+use crate::{config::ScheduleConfig, error::BusError};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Fixed interval whose phase is set by `anchor`, so restarts and long backups don't drift later runs.
+    Interval { every: Duration, anchor: Anchor },
+    /// Standard 5-field (or 6-field with seconds) cron expression evaluated in `timezone`.
+    Cron { cron: Box<Cron>, timezone: Tz },
+}
+
+#[derive(Debug, Clone)]
+pub enum Anchor {
+    /// No `start_time`: runs are counted from when the scheduler started.
+    Started(DateTime<Utc>),
+    /// Runs are aligned to `start_time` wall-clock time in `timezone`.
+    WallClock { start_time: NaiveTime, timezone: Tz },
+}
+
+impl Schedule {
+    pub fn from_config(config: &ScheduleConfig, now: DateTime<Utc>) -> Result<Self, BusError> {
+        let timezone = parse_timezone(config.timezone.as_deref())?;
+        let start_time = config
+            .start_time
+            .as_deref()
+            .map(parse_start_time)
+            .transpose()?;
+
+        match (&config.cron, config.interval_seconds) {
+            (Some(_), Some(_)) => Err(BusError::Config(
+                "Only one of 'cron' or 'interval_seconds' can be set in a schedule".into(),
+            )),
+            (Some(_), None) if start_time.is_some() => Err(BusError::Config(
+                "'start_time' cannot be combined with 'cron'; put the time in the expression"
+                    .into(),
+            )),
+            (Some(expression), None) => {
+                let cron = Cron::from_str(expression).map_err(|e| {
+                    BusError::Config(format!("Invalid cron expression '{}': {}", expression, e))
+                })?;
+                Ok(Schedule::Cron {
+                    cron: Box::new(cron),
+                    timezone,
+                })
+            }
+            (None, Some(0)) => Err(BusError::Config(
+                "'interval_seconds' must be greater than zero".into(),
+            )),
+            (None, Some(seconds)) => Ok(Schedule::Interval {
+                every: Duration::seconds(seconds as i64),
+                anchor: match start_time {
+                    Some(start_time) => Anchor::WallClock {
+                        start_time,
+                        timezone,
+                    },
+                    None => Anchor::Started(now),
+                },
+            }),
+            (None, None) => Err(BusError::Config(
+                "A schedule needs either 'cron' or 'interval_seconds' to be set".into(),
+            )),
+        }
+    }
+
+    /// When the very first backup should happen after the scheduler starts.
+    ///
+    /// Interval schedules without a `start_time` back up right away, as they always have.
+    pub fn first_run(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, BusError> {
+        match self {
+            Schedule::Interval {
+                anchor: Anchor::Started(started),
+                ..
+            } => Ok(*started),
+            _ => self.next_run(now),
+        }
+    }
+
+    /// Next fire time strictly after `after`.
+    pub fn next_run(&self, after: DateTime<Utc>) -> Result<DateTime<Utc>, BusError> {
+        match self {
+            Schedule::Interval {
+                every,
+                anchor: Anchor::Started(started),
+            } => Ok(next_step(*started, *every, after)),
+            Schedule::Interval {
+                every,
+                anchor:
+                    Anchor::WallClock {
+                        start_time,
+                        timezone,
+                    },
+            } => Ok(next_wall_clock_run(*every, *start_time, timezone, after)),
+            Schedule::Cron { cron, timezone } => {
+                let local_after = after.with_timezone(timezone);
+                let next = cron
+                    .find_next_occurrence(&local_after, false)
+                    .map_err(|e| {
+                        BusError::Config(format!("Failed to compute next cron occurrence: {}", e))
+                    })?;
+
+                Ok(next.with_timezone(&Utc))
+            }
+        }
+    }
+}
+
+/// First `anchor + k * every` strictly after `after`.
+fn next_step(anchor: DateTime<Utc>, every: Duration, after: DateTime<Utc>) -> DateTime<Utc> {
+    if after < anchor {
+        return anchor;
+    }
+
+    let elapsed = (after - anchor).num_milliseconds();
+    let step = every.num_milliseconds();
+    let periods = elapsed / step + 1;
+
+    anchor + Duration::milliseconds(periods * step)
+}
+
+fn next_wall_clock_run(
+    every: Duration,
+    start_time: NaiveTime,
+    timezone: &Tz,
+    after: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let today = after.with_timezone(timezone).date_naive();
+    let seconds = every.num_seconds();
+
+    if SECONDS_PER_DAY % seconds == 0 {
+        // Intervals that divide a day re-anchor on every local day so the phase follows DST.
+        let mut day = today;
+        if resolve_local(day, start_time, timezone) > after {
+            day = day.pred_opt().unwrap_or(day);
+        }
+
+        let day_anchor = resolve_local(day, start_time, timezone);
+        let next_day_anchor = day
+            .succ_opt()
+            .map(|next_day| resolve_local(next_day, start_time, timezone))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+        next_step(day_anchor, every, after).min(next_day_anchor)
+    } else if seconds % SECONDS_PER_DAY == 0 {
+        // Whole-day intervals pick calendar days counted from the epoch so restarts keep the cycle.
+        let days = seconds / SECONDS_PER_DAY;
+        let epoch = NaiveDate::default();
+        let mut day = today.pred_opt().unwrap_or(today);
+
+        loop {
+            if (day - epoch).num_days().rem_euclid(days) == 0 {
+                let candidate = resolve_local(day, start_time, timezone);
+                if candidate > after {
+                    return candidate;
+                }
+            }
+            day = match day.succ_opt() {
+                Some(next_day) => next_day,
+                None => return DateTime::<Utc>::MAX_UTC,
+            };
+        }
+    } else {
+        // Anything else counts fixed steps from `start_time` on the epoch; re-anchoring daily
+        // would cut the last interval before `start_time` short.
+        let epoch_anchor = resolve_local(NaiveDate::default(), start_time, timezone);
+        next_step(epoch_anchor, every, after)
+    }
+}
+
+/// Maps a local wall-clock time to UTC, taking the earlier instant when the clock is
+/// turned back and the first valid instant after a gap when it is turned forward.
+fn resolve_local(date: NaiveDate, time: NaiveTime, timezone: &Tz) -> DateTime<Utc> {
+    let mut local = date.and_time(time);
+
+    loop {
+        match timezone.from_local_datetime(&local) {
+            LocalResult::Single(resolved) | LocalResult::Ambiguous(resolved, _) => {
+                return resolved.with_timezone(&Utc);
+            }
+            LocalResult::None => local += Duration::minutes(15),
+        }
+    }
+}
+
+pub fn parse_timezone(timezone: Option<&str>) -> Result<Tz, BusError> {
+    match timezone {
+        Some(name) => {
+            Tz::from_str(name).map_err(|_| BusError::Config(format!("Unknown timezone: {}", name)))
+        }
+        None => Ok(Tz::UTC),
+    }
+}
+
+pub fn parse_start_time(start_time: &str) -> Result<NaiveTime, BusError> {
+    NaiveTime::parse_from_str(start_time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(start_time, "%H:%M:%S"))
+        .map_err(|_| {
+            BusError::Config(format!(
+                "Invalid start_time '{}', expected HH:MM",
+                start_time
+            ))
+        })
+}
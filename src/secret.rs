@@ -0,0 +1,93 @@
+use std::fmt::{Debug, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::BusError;
+
+/// A credential as configured: the value itself, `file:<path>` to read it from a file such
+/// as a Docker or Kubernetes secret, or `cmd:<command>` to take the first line a helper
+/// prints. References are resolved every time the secret is used, so rotated secrets are
+/// picked up without a restart and never live in the daemon's environment.
+///
+/// Debug, Display and Serialize never show the value, nor the command, which may embed a
+/// token; only `file:` paths are shown.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Whether nothing was configured; a reference that resolves to nothing is not empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub async fn resolve(&self) -> Result<String, BusError> {
+        let secret = self.clone();
+        tokio::task::spawn_blocking(move || secret.resolve_blocking())
+            .await
+            .map_err(|e| BusError::Secret(e.to_string()))?
+    }
+
+    /// Like `resolve`, for callers already running in a blocking context.
+    pub fn resolve_blocking(&self) -> Result<String, BusError> {
+        if let Some(path) = self.0.strip_prefix("file:") {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| BusError::Secret(format!("failed to read {}: {}", path, e)))?;
+
+            // Secret files usually end with a newline that isn't part of the secret.
+            return Ok(contents.trim_end_matches(['\r', '\n']).to_string());
+        }
+
+        if let Some(command) = self.0.strip_prefix("cmd:") {
+            let output = std::process::Command::new("sh")
+                .args(["-c", command])
+                .stdin(std::process::Stdio::null())
+                .output()
+                .map_err(|e| BusError::Secret(format!("failed to run {}: {}", self, e)))?;
+
+            if !output.status.success() {
+                return Err(BusError::Secret(format!(
+                    "{} failed with {}: {}",
+                    self,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            return Ok(stdout.lines().next().unwrap_or_default().to_string());
+        }
+
+        Ok(self.0.clone())
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.starts_with("file:") {
+            write!(f, "{}", self.0)
+        } else if self.0.starts_with("cmd:") {
+            write!(f, "cmd:***")
+        } else {
+            write!(f, "***")
+        }
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({})", self)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ServiceType, error::BusError, secret::Secret};
+
+/// A program that writes a dump, run directly rather than through a shell.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CommandConnectionConfig {
+    pub service_type: ServiceType,
+    /// Looked up in `PATH` unless it is a path.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Added to Bus's own environment; values can be secret references.
+    #[serde(default)]
+    pub env: BTreeMap<String, Secret>,
+    pub working_dir: Option<String>,
+    /// Kills the command, and everything it started, after this many seconds.
+    pub timeout_seconds: Option<u64>,
+    /// File the command writes its dump to, relative to `working_dir`; without it, the
+    /// dump is what it prints.
+    pub output_file: Option<String>,
+    /// Extension of the artifact name before compression and encryption.
+    #[serde(default = "default_extension")]
+    pub extension: String,
+}
+
+impl CommandConnectionConfig {
+    pub fn check(&self) -> Result<(), BusError> {
+        if self.command.trim().is_empty() {
+            return Err(BusError::Config("'command' must not be empty".into()));
+        }
+        if self.timeout_seconds == Some(0) {
+            return Err(BusError::Config(
+                "'timeout_seconds' must be greater than zero".into(),
+            ));
+        }
+        if self.extension.is_empty() || self.extension.contains(['/', '\\', '.']) {
+            return Err(BusError::Config(format!(
+                "'extension' must be a single name part like 'ldif', not '{}'",
+                self.extension
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_extension() -> String {
+    "out".to_string()
+}
@@ -0,0 +1,177 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use tracing::info;
+
+use crate::{
+    common::BackupService,
+    config::{ScheduleConfig, ServiceConfig, ServiceType},
+    error::BusError,
+    pipeline::{Artifact, ArtifactPipeline, kill_process_group},
+    service::command::config::CommandConnectionConfig,
+};
+
+pub mod config;
+
+/// `backup_options` keys; the command's own options are its `args`.
+pub const BACKUP_OPTIONS: [&str; 0] = [];
+
+/// `restore_options` keys; command services can't be restored by Bus.
+pub const RESTORE_OPTIONS: [&str; 0] = [];
+
+/// Schedules a dump script Bus knows nothing about; what it prints, or the file it writes,
+/// is compressed, encrypted, stored and pruned like any other dump.
+pub struct CommandJob {
+    service_type: ServiceType,
+    alias: String,
+    schedule: ScheduleConfig,
+    connection: CommandConnectionConfig,
+    backup_options: Option<std::collections::HashMap<String, String>>,
+    pipeline: ArtifactPipeline,
+}
+
+impl CommandJob {
+    pub fn new(config: ServiceConfig, pipeline: ArtifactPipeline) -> Self {
+        Self {
+            service_type: ServiceType::Command,
+            alias: config.alias,
+            schedule: config.schedule,
+            connection: config.connection.as_command().unwrap().clone(),
+            backup_options: config.backup_options,
+            pipeline,
+        }
+    }
+
+    /// The configured command, with its secrets resolved right before it runs.
+    async fn command(&self) -> Result<tokio::process::Command, BusError> {
+        let mut cmd = tokio::process::Command::new(&self.connection.command);
+        cmd.args(&self.connection.args);
+
+        for (key, value) in &self.connection.env {
+            cmd.env(key, value.resolve().await?);
+        }
+        if let Some(working_dir) = &self.connection.working_dir {
+            cmd.current_dir(working_dir);
+        }
+
+        Ok(cmd)
+    }
+
+    /// `output_file`, relative to `working_dir` like the command sees it.
+    fn output_path(&self, output_file: &str) -> PathBuf {
+        match &self.connection.working_dir {
+            Some(working_dir) => Path::new(working_dir).join(output_file),
+            None => PathBuf::from(output_file),
+        }
+    }
+
+    fn limit(&self) -> Option<Duration> {
+        self.connection.timeout_seconds.map(Duration::from_secs)
+    }
+
+    /// Runs a command that writes its dump to `output_file` and waits for it to succeed.
+    async fn run_to_file(&self, mut cmd: tokio::process::Command) -> Result<(), BusError> {
+        let tool = self.connection.command.as_str();
+        let limit = self.limit();
+
+        if limit.is_some() {
+            cmd.process_group(0);
+        }
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| BusError::tool_unavailable(tool, e))?;
+        let pid = child.id();
+
+        let output = match limit {
+            None => child.wait_with_output().await,
+            Some(limit) => match tokio::time::timeout(limit, child.wait_with_output()).await {
+                Ok(output) => output,
+                Err(_) => {
+                    kill_process_group(pid);
+                    return Err(BusError::tool_timed_out(tool, limit));
+                }
+            },
+        }
+        .map_err(|e| BusError::tool_unavailable(tool, e))?;
+
+        if !output.status.success() {
+            return Err(BusError::tool_failed(tool, &output));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl BackupService for CommandJob {
+    fn service_type(&self) -> &ServiceType {
+        &self.service_type
+    }
+
+    fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    fn backup_options(&self) -> Option<&std::collections::HashMap<String, String>> {
+        self.backup_options.as_ref()
+    }
+
+    async fn backup(&self, timestamp: &str) -> Result<Artifact, BusError> {
+        let backup_file = format!(
+            "command_{}_{}.{}",
+            self.alias(),
+            timestamp,
+            self.connection.extension
+        );
+        let tool = self.connection.command.as_str();
+
+        info!(
+            "Creating command backup for {} with {}: {}",
+            self.alias(),
+            tool,
+            self.pipeline.artifact_path(&backup_file),
+        );
+
+        let cmd = self.command().await?;
+        let artifact = match &self.connection.output_file {
+            None => {
+                self.pipeline
+                    .capture(cmd, tool, &backup_file, self.limit())
+                    .await?
+            }
+            Some(output_file) => {
+                let output_file = self.output_path(output_file);
+                // A file left by an earlier run must not pass for this run's dump.
+                let _ = tokio::fs::remove_file(&output_file).await;
+
+                let artifact = match self.run_to_file(cmd).await {
+                    Ok(()) => self.pipeline.ingest(&output_file, tool, &backup_file).await,
+                    Err(e) => Err(e),
+                };
+                // The plain dump is only kept in the artifact.
+                let _ = tokio::fs::remove_file(&output_file).await;
+                artifact?
+            }
+        };
+
+        info!(
+            "Command backup written with {} for {}: {}",
+            self.pipeline,
+            self.alias(),
+            artifact.path
+        );
+
+        Ok(artifact)
+    }
+
+    fn get_schedule(&self) -> &ScheduleConfig {
+        &self.schedule
+    }
+}
@@ -1,22 +1,64 @@
 use crate::{
     common::BackupService,
-    config::{ServiceConfig, ServiceType},
-    service::{postgres::PostgresJob, redis::RedisJob},
+    compression::{self, Compression},
+    config::{CommonConfig, ServiceConfig, ServiceType},
+    encryption::Encryption,
+    error::BusError,
+    pipeline::ArtifactPipeline,
+    service::{command::CommandJob, mysql::MySqlJob, postgres::PostgresJob, redis::RedisJob},
 };
 
+pub mod command;
+pub mod mysql;
 pub mod postgres;
 pub mod redis;
 
+/// Directory under `common.backup_dir` where artifacts are written before they reach their destinations.
+pub const STAGING_DIR: &str = ".staging";
+
 pub struct ServiceFactory;
 
+impl ServiceType {
+    /// Keys `backup_options` accepts for this type of service.
+    pub fn backup_options(&self) -> Vec<&'static str> {
+        let specific: &[&str] = match self {
+            ServiceType::Postgres => &postgres::BACKUP_OPTIONS,
+            ServiceType::Redis => &redis::BACKUP_OPTIONS,
+            ServiceType::Command => &command::BACKUP_OPTIONS,
+            ServiceType::MySql => &mysql::BACKUP_OPTIONS,
+        };
+
+        [specific, &compression::BACKUP_OPTIONS].concat()
+    }
+
+    /// Keys `restore_options` accepts for this type of service.
+    pub fn restore_options(&self) -> Vec<&'static str> {
+        match self {
+            ServiceType::Postgres => postgres::RESTORE_OPTIONS.to_vec(),
+            ServiceType::Redis => redis::RESTORE_OPTIONS.to_vec(),
+            ServiceType::Command => command::RESTORE_OPTIONS.to_vec(),
+            ServiceType::MySql => mysql::RESTORE_OPTIONS.to_vec(),
+        }
+    }
+}
+
 impl ServiceFactory {
     pub fn create_service(
         config: ServiceConfig,
-        backup_dir: String,
-    ) -> Result<Box<dyn BackupService>, Box<dyn std::error::Error + Send + Sync>> {
+        common: &CommonConfig,
+    ) -> Result<Box<dyn BackupService>, BusError> {
+        let staging_dir = std::path::Path::new(&common.backup_dir).join(STAGING_DIR);
+        let compression =
+            Compression::resolve(common.compression.as_ref(), config.backup_options.as_ref())?;
+        let encryption =
+            Encryption::resolve(common.encryption.as_ref(), config.encryption.as_ref())?;
+        let pipeline = ArtifactPipeline::new(staging_dir, compression, encryption);
+
         match config.service_type {
-            ServiceType::Postgres => Ok(Box::new(PostgresJob::new(config, backup_dir))),
-            ServiceType::Redis => Ok(Box::new(RedisJob::new(config, backup_dir))),
+            ServiceType::Postgres => Ok(Box::new(PostgresJob::new(config, pipeline))),
+            ServiceType::Redis => Ok(Box::new(RedisJob::new(config, pipeline))),
+            ServiceType::Command => Ok(Box::new(CommandJob::new(config, pipeline))),
+            ServiceType::MySql => Ok(Box::new(MySqlJob::new(config, pipeline))),
             // _ => Err(format!("Unknown service type: {}", config.service_type).into()),
         }
     }
@@ -0,0 +1,139 @@
+use std::{
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ServiceType, error::BusError, secret::Secret};
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MySqlConnectionConfig {
+    pub service_type: ServiceType,
+    pub host: String,
+    #[serde(default = "default_mysql_port")]
+    pub port: u16,
+    #[serde(alias = "user")]
+    pub username: String,
+    password: Option<Secret>,
+    /// Databases to dump; all of them when left out.
+    pub databases: Option<Vec<String>>,
+    pub tls: Option<MySqlTlsConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct MySqlTlsConfig {
+    pub ca: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    #[serde(default)]
+    pub verify_server_cert: bool,
+}
+
+impl MySqlConnectionConfig {
+    pub fn check(&self) -> Result<(), BusError> {
+        match &self.databases {
+            Some(databases) if databases.is_empty() => Err(BusError::Config(
+                "'databases' must list at least one database; leave it out to dump all of them"
+                    .into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep the returned option file until the client has exited.
+    pub async fn connect(
+        &self,
+        cmd: &mut tokio::process::Command,
+        mariadb: bool,
+    ) -> Result<Option<OptionFile>, BusError> {
+        let option_file = match &self.password {
+            Some(password) => Some(OptionFile::new(&password.resolve().await?)?),
+            None => None,
+        };
+        if let Some(option_file) = &option_file {
+            // Must be the first argument.
+            cmd.arg(format!("--defaults-extra-file={}", option_file.0.display()));
+        }
+
+        // `localhost` would otherwise mean the local socket, not the port.
+        cmd.args([
+            "--protocol=TCP",
+            "-h",
+            self.host.as_str(),
+            "-P",
+            self.port.to_string().as_str(),
+            "-u",
+            self.username.as_str(),
+        ]);
+
+        if let Some(tls) = &self.tls {
+            match (mariadb, tls.verify_server_cert) {
+                (true, verify) => {
+                    cmd.arg("--ssl");
+                    if verify {
+                        cmd.arg("--ssl-verify-server-cert");
+                    }
+                }
+                (false, true) => {
+                    cmd.arg("--ssl-mode=VERIFY_IDENTITY");
+                }
+                (false, false) => {
+                    cmd.arg("--ssl-mode=REQUIRED");
+                }
+            }
+            for (flag, path) in [
+                ("--ssl-ca", &tls.ca),
+                ("--ssl-cert", &tls.cert),
+                ("--ssl-key", &tls.key),
+            ] {
+                if let Some(path) = path {
+                    cmd.arg(format!("{}={}", flag, path));
+                }
+            }
+        }
+
+        Ok(option_file)
+    }
+}
+
+/// Holds the password for the client; removed on drop.
+pub struct OptionFile(PathBuf);
+
+impl OptionFile {
+    fn new(password: &str) -> Result<Self, BusError> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "bus-mysql-{}-{}.cnf",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let escaped = password
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| write!(file, "[client]\npassword=\"{}\"\n", escaped))
+            .map_err(|e| BusError::storage(&path, e))?;
+
+        Ok(Self(path))
+    }
+}
+
+impl Drop for OptionFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn default_mysql_port() -> u16 {
+    3306
+}
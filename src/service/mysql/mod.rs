@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use tracing::info;
+
+use crate::common::{BackupService, Capabilities, RestoreOptions};
+use crate::config::{ScheduleConfig, ServiceConfig, ServiceType};
+use crate::error::BusError;
+use crate::pipeline::{self, Artifact, ArtifactPipeline, tool_version};
+use crate::service::mysql::config::{MySqlConnectionConfig, OptionFile};
+
+pub mod config;
+
+/// `backup_options` keys passed on to the dump tool; `tool` picks the dump tool itself.
+pub const BACKUP_OPTIONS: [&str; 5] = ["tool", "routines", "triggers", "events", "exclude_table"];
+
+/// `restore_options` keys; dumps are replayed as written.
+pub const RESTORE_OPTIONS: [&str; 0] = [];
+
+pub struct MySqlJob {
+    service_type: ServiceType,
+    alias: String,
+    schedule: ScheduleConfig,
+    connection: MySqlConnectionConfig,
+    backup_options: Option<std::collections::HashMap<String, String>>,
+    pipeline: ArtifactPipeline,
+}
+
+/// The first installed of `candidates`, and whether it is MariaDB's.
+async fn find_tool(candidates: &[&str]) -> (String, bool) {
+    for program in candidates {
+        if let Some(version) = tool_version(program.as_ref()).await {
+            return (program.to_string(), version.contains("MariaDB"));
+        }
+    }
+
+    (candidates[candidates.len() - 1].to_string(), false)
+}
+
+impl MySqlJob {
+    pub fn new(config: ServiceConfig, pipeline: ArtifactPipeline) -> Self {
+        Self {
+            service_type: ServiceType::MySql,
+            alias: config.alias,
+            schedule: config.schedule,
+            connection: config.connection.as_mysql().unwrap().clone(),
+            backup_options: config.backup_options,
+            pipeline,
+        }
+    }
+
+    fn option(&self, key: &str) -> Option<&str> {
+        self.backup_options
+            .as_ref()
+            .and_then(|options| options.get(key))
+            .map(String::as_str)
+    }
+
+    async fn client(
+        &self,
+    ) -> Result<(tokio::process::Command, String, Option<OptionFile>), BusError> {
+        let (program, mariadb) = find_tool(&["mariadb", "mysql"]).await;
+        let mut cmd = tokio::process::Command::new(&program);
+        let option_file = self.connection.connect(&mut cmd, mariadb).await?;
+
+        Ok((cmd, program, option_file))
+    }
+
+    async fn query(&self, sql: &str) -> Result<String, BusError> {
+        let (mut cmd, tool, _option_file) = self.client().await?;
+        let output = cmd
+            .args(["-N", "-B", "-e", sql])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await
+            .map_err(|e| BusError::tool_unavailable(&tool, e))?;
+
+        if !output.status.success() {
+            return Err(BusError::tool_failed(&tool, &output));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl BackupService for MySqlJob {
+    fn service_type(&self) -> &ServiceType {
+        &self.service_type
+    }
+
+    fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    fn backup_options(&self) -> Option<&std::collections::HashMap<String, String>> {
+        self.backup_options.as_ref()
+    }
+
+    async fn backup(&self, timestamp: &str) -> Result<Artifact, BusError> {
+        let backup_file = format!("mysql_{}_{}.sql", self.alias(), timestamp);
+
+        info!(
+            "Creating MySQL backup for {}: {}",
+            self.alias(),
+            self.pipeline.artifact_path(&backup_file),
+        );
+
+        let (program, mariadb) = match self.option("tool") {
+            Some(tool) => find_tool(&[tool]).await,
+            None => find_tool(&["mariadb-dump", "mysqldump"]).await,
+        };
+        let mut cmd = tokio::process::Command::new(&program);
+        let option_file = self.connection.connect(&mut cmd, mariadb).await?;
+
+        cmd.arg("--single-transaction");
+        if self.option("routines") == Some("true") {
+            cmd.arg("--routines");
+        }
+        if self.option("triggers") == Some("false") {
+            cmd.arg("--skip-triggers");
+        }
+        if self.option("events") == Some("true") {
+            cmd.arg("--events");
+        }
+        for table in self
+            .option("exclude_table")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|table| !table.is_empty())
+        {
+            if !table.contains('.') {
+                return Err(BusError::Config(format!(
+                    "'exclude_table' needs the database of '{}', e.g. app.{}",
+                    table, table
+                )));
+            }
+            cmd.arg(format!("--ignore-table={}", table));
+        }
+
+        match &self.connection.databases {
+            Some(databases) => {
+                cmd.arg("--databases").args(databases);
+            }
+            None => {
+                cmd.arg("--all-databases");
+            }
+        }
+
+        let mut artifact = self.pipeline.run(cmd, &program, &backup_file).await?;
+        drop(option_file);
+        artifact.server_version = self.query("SELECT VERSION()").await.ok();
+
+        info!(
+            "MySQL backup written with {} for {}: {}",
+            self.pipeline,
+            self.alias(),
+            artifact.path
+        );
+
+        Ok(artifact)
+    }
+
+    async fn restore(&self, artifact: &Path, options: &RestoreOptions) -> Result<(), BusError> {
+        if options.target_db.is_some() {
+            return Err(BusError::Config(
+                "MySQL dumps name the databases they restore; --target-db does not apply".into(),
+            ));
+        }
+
+        let (cmd, tool, _option_file) = self.client().await?;
+        info!(
+            "Restoring {} into {} with {}",
+            artifact.display(),
+            self.alias(),
+            tool
+        );
+        let restored = pipeline::feed(cmd, &tool, artifact, options.keys.as_ref()).await?;
+        info!("Restored {} bytes into '{}'", restored, self.alias());
+
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<String, BusError> {
+        self.query("SELECT VERSION()").await
+    }
+
+    fn describe(&self) -> Capabilities {
+        Capabilities {
+            restore: true,
+            verify: false,
+            health_check: true,
+        }
+    }
+
+    fn get_schedule(&self) -> &ScheduleConfig {
+        &self.schedule
+    }
+
+    fn is_retryable(&self, error: &BusError) -> bool {
+        match error {
+            BusError::DumpTool { stderr, .. }
+                if stderr.contains("Access denied") || stderr.contains("Unknown database") =>
+            {
+                false
+            }
+            _ => error.is_retryable(),
+        }
+    }
+}
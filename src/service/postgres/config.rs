@@ -1,6 +1,12 @@
+use std::net::ToSocketAddrs;
+
 use serde::{Deserialize, Serialize};
 
-use crate::{config::ServiceType, utils::deserialize_with_env};
+use crate::{
+    config::{ServiceType, VerifyConfig},
+    error::BusError,
+    secret::Secret,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PostgresConnectionConfig {
@@ -9,20 +15,84 @@ pub struct PostgresConnectionConfig {
     #[serde(default = "default_postgres_port")]
     pub port: u16,
     pub username: String,
-    #[serde(deserialize_with = "deserialize_with_env")]
-    password: String,
+    /// Without a password, pg_dump looks it up in `passfile` or `~/.pgpass`.
+    password: Option<Secret>,
+    /// pgpass-format file used instead of `~/.pgpass`.
+    pub passfile: Option<String>,
     pub database: String,
     pub schema: Option<String>,
     pub ssl_mode: Option<String>,
     pub connection_timeout: Option<u64>,
+    /// Directory with pg_dump, psql and pg_restore; found on `PATH` when unset.
+    pub bin_dir: Option<String>,
 }
 
 impl PostgresConnectionConfig {
-    pub fn get_password(&self) -> String {
-        self.password.clone()
+    /// Passes the credentials to a libpq tool through its environment, resolving the
+    /// password right before the tool runs.
+    pub async fn authenticate(&self, cmd: &mut tokio::process::Command) -> Result<(), BusError> {
+        match &self.password {
+            Some(password) => {
+                cmd.env("PGPASSWORD", password.resolve().await?);
+            }
+            None => {
+                if let Some(passfile) = &self.passfile {
+                    cmd.env("PGPASSFILE", passfile);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The server `verify` restores into, with the same credentials, and the scratch
+    /// database there. It is dropped after every check, so it can't be the one backed up.
+    pub fn scratch(&self, verify: &VerifyConfig, alias: &str) -> Result<(Self, String), BusError> {
+        let mut scratch = self.clone();
+        if let Some(host) = &verify.host {
+            scratch.host = host.clone();
+        }
+        if let Some(port) = verify.port {
+            scratch.port = port;
+        }
+        let database = verify
+            .database
+            .clone()
+            .unwrap_or_else(|| format!("bus_verify_{}", alias));
+
+        if scratch.port == self.port
+            && database == self.database
+            && same_server(&scratch.host, &self.host, self.port)
+        {
+            return Err(BusError::Config(format!(
+                "'{}' is the database being backed up; verification drops its scratch database",
+                database
+            )));
+        }
+
+        Ok((scratch, database))
     }
 }
 
+/// Whether `a` and `b` name the same server, e.g. `localhost` and `127.0.0.1`.
+fn same_server(a: &str, b: &str, port: u16) -> bool {
+    if a == b {
+        return true;
+    }
+
+    let resolve = |host: &str| -> Vec<std::net::IpAddr> {
+        (host, port)
+            .to_socket_addrs()
+            .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+            .unwrap_or_default()
+    };
+    let (a, b) = (resolve(a), resolve(b));
+    a.iter().any(|a| {
+        b.iter()
+            .any(|b| a == b || (a.is_loopback() && b.is_loopback()))
+    })
+}
+
 fn default_postgres_port() -> u16 {
     5432
 }
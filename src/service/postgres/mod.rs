@@ -1,30 +1,247 @@
+use std::{path::Path, time::Instant};
+
 use tracing::{info, warn};
 
-use crate::common::BackupService;
-use crate::config::{ScheduleConfig, ServiceConfig, ServiceType};
+use crate::common::{BackupService, Capabilities, RestoreOptions, Verification};
+use crate::config::{ScheduleConfig, ServiceConfig, ServiceType, VerifyConfig};
+use crate::error::BusError;
+use crate::pipeline::{self, Artifact, ArtifactPipeline};
 use crate::service::postgres::config::PostgresConnectionConfig;
 
 pub mod config;
 
+/// `backup_options` keys passed on to pg_dump.
+pub const BACKUP_OPTIONS: [&str; 4] = ["schema_only", "data_only", "format", "exclude_table"];
+
+/// `restore_options` keys; `clean` drops existing objects before pg_restore recreates them.
+pub const RESTORE_OPTIONS: [&str; 1] = ["clean"];
+
 pub struct PostgresJob {
     service_type: ServiceType,
     alias: String,
     schedule: ScheduleConfig,
     connection: PostgresConnectionConfig,
     backup_options: Option<std::collections::HashMap<String, String>>,
-    backup_dir: String,
+    restore_options: Option<std::collections::HashMap<String, String>>,
+    verify: Option<VerifyConfig>,
+    pipeline: ArtifactPipeline,
+}
+
+/// A libpq tool connected to `database` on `connection`, never prompting for a password.
+async fn command(
+    connection: &PostgresConnectionConfig,
+    program: &str,
+    database: &str,
+) -> Result<tokio::process::Command, BusError> {
+    let mut cmd = match &connection.bin_dir {
+        Some(bin_dir) => tokio::process::Command::new(Path::new(bin_dir).join(program)),
+        None => tokio::process::Command::new(program),
+    };
+    connection.authenticate(&mut cmd).await?;
+    cmd.args([
+        "-h",
+        connection.host.as_str(),
+        "-p",
+        connection.port.to_string().as_str(),
+        "-U",
+        connection.username.as_str(),
+        "-d",
+        database,
+        "--no-password",
+    ]);
+
+    Ok(cmd)
+}
+
+/// Runs `sql` with psql and returns what it prints, without headers or alignment.
+async fn query(
+    connection: &PostgresConnectionConfig,
+    database: &str,
+    sql: &str,
+) -> Result<String, BusError> {
+    let output = command(connection, "psql", database)
+        .await?
+        .args(["-XtAq", "-v", "ON_ERROR_STOP=1", "-c", sql])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|e| BusError::tool_unavailable("psql", e))?;
+
+    if !output.status.success() {
+        return Err(BusError::tool_failed("psql", &output));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quotes `table` or `schema.table`, keeping the case of each part.
+fn quote_table(table: &str) -> String {
+    table
+        .split('.')
+        .map(quote_identifier)
+        .collect::<Vec<_>>()
+        .join(".")
 }
 
 impl PostgresJob {
-    pub fn new(config: ServiceConfig, backup_dir: String) -> Self {
+    pub fn new(config: ServiceConfig, pipeline: ArtifactPipeline) -> Self {
         Self {
             service_type: ServiceType::Postgres,
             alias: config.alias,
             schedule: config.schedule,
             connection: config.connection.as_postgres().unwrap().clone(),
             backup_options: config.backup_options,
-            backup_dir,
+            restore_options: config.restore_options,
+            verify: config.verify,
+            pipeline,
+        }
+    }
+
+    /// Best effort; a missing `psql` only leaves the version out of the manifest.
+    async fn server_version(&self) -> Option<String> {
+        query(
+            &self.connection,
+            &self.connection.database,
+            "SHOW server_version",
+        )
+        .await
+        .ok()
+        .filter(|version| !version.is_empty())
+    }
+
+    /// Plain dumps are replayed with psql, custom and tar archives with pg_restore, each in
+    /// a single transaction that stops at the first error.
+    async fn load(
+        &self,
+        connection: &PostgresConnectionConfig,
+        database: &str,
+        artifact: &Path,
+        options: &RestoreOptions,
+        clean: bool,
+    ) -> Result<(), BusError> {
+        let format = options
+            .backup_options
+            .get("format")
+            .map(String::as_str)
+            .unwrap_or("plain");
+
+        let (mut cmd, tool) = match format {
+            "plain" | "p" if clean => {
+                return Err(BusError::Config(
+                    "'clean' needs a custom or tar format backup; plain dumps are replayed as written"
+                        .into(),
+                ));
+            }
+            "plain" | "p" => (command(connection, "psql", database).await?, "psql"),
+            "custom" | "c" | "tar" | "t" => (
+                command(connection, "pg_restore", database).await?,
+                "pg_restore",
+            ),
+            _ => {
+                return Err(BusError::Config(format!(
+                    "Cannot restore pg_dump format '{}'",
+                    format
+                )));
+            }
+        };
+
+        if tool == "psql" {
+            cmd.args(["-X", "-q", "-v", "ON_ERROR_STOP=1", "--single-transaction"]);
+        } else {
+            cmd.args(["--exit-on-error", "--single-transaction"]);
+            if clean {
+                cmd.args(["--clean", "--if-exists"]);
+            }
+        }
+
+        info!(
+            "Restoring {} into database '{}' of {} with {}",
+            artifact.display(),
+            database,
+            self.alias(),
+            tool
+        );
+        let restored = pipeline::feed(cmd, tool, artifact, options.keys.as_ref()).await?;
+        info!("Restored {} bytes into '{}'", restored, database);
+
+        Ok(())
+    }
+
+    /// Everything `verify` checks; the scratch database is left for the caller to drop.
+    async fn check_restore(
+        &self,
+        scratch: &PostgresConnectionConfig,
+        database: &str,
+        config: &VerifyConfig,
+        artifact: &Path,
+        options: &RestoreOptions,
+        verification: &mut Verification,
+    ) -> Result<(), String> {
+        let database_name = quote_identifier(database);
+        // Left over if Bus was stopped during an earlier check.
+        query(
+            scratch,
+            "postgres",
+            &format!("DROP DATABASE IF EXISTS {}", database_name),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        query(
+            scratch,
+            "postgres",
+            &format!("CREATE DATABASE {}", database_name),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        self.load(scratch, database, artifact, options, false)
+            .await
+            .map_err(|e| format!("restore failed: {}", e))?;
+
+        let tables = query(
+            scratch,
+            database,
+            "SELECT count(*) FROM information_schema.tables \
+             WHERE table_schema NOT IN ('pg_catalog', 'information_schema')",
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        let tables = tables.parse::<u64>().unwrap_or_default();
+        verification.tables = Some(tables);
+        if tables < config.min_tables {
+            return Err(format!(
+                "{} tables restored, expected at least {}",
+                tables, config.min_tables
+            ));
+        }
+
+        for table in &config.tables {
+            let rows = query(
+                scratch,
+                database,
+                &format!("SELECT count(*) FROM {}", quote_table(table)),
+            )
+            .await
+            .map_err(|e| format!("counting rows of '{}' failed: {}", table, e))?;
+            verification
+                .row_counts
+                .insert(table.clone(), rows.parse().unwrap_or_default());
         }
+
+        if let Some(sql) = &config.sql {
+            let result = query(scratch, database, sql)
+                .await
+                .map_err(|e| format!("check query failed: {}", e))?;
+            if result != "t" {
+                return Err(format!("check query returned '{}' instead of true", result));
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -38,43 +255,21 @@ impl BackupService for PostgresJob {
         &self.alias
     }
 
-    fn backup_dir(&self) -> &str {
-        &self.backup_dir
+    fn backup_options(&self) -> Option<&std::collections::HashMap<String, String>> {
+        self.backup_options.as_ref()
     }
 
-    async fn backup(
-        &self,
-        timestamp: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let backup_file = format!(
-            "{}/postgres_{}_{}.sql",
-            self.backup_dir(),
-            self.alias(),
-            timestamp
-        );
+    async fn backup(&self, timestamp: &str) -> Result<Artifact, BusError> {
+        let backup_file = format!("postgres_{}_{}.sql", self.alias(), timestamp);
 
         info!(
             "Creating PostgreSQL backup for {}: {}",
             self.alias(),
-            backup_file,
+            self.pipeline.artifact_path(&backup_file),
         );
 
-        let mut cmd = tokio::process::Command::new("pg_dump");
-        cmd.env("PGPASSWORD", &self.connection.get_password())
-            .args([
-                "-h",
-                self.connection.host.as_str(),
-                "-p",
-                self.connection.port.to_string().as_str(),
-                "-U",
-                self.connection.username.as_str(),
-                "-d",
-                self.connection.database.as_str(),
-                "-f",
-                &backup_file,
-                "--verbose",
-                "--no-password",
-            ]);
+        let mut cmd = command(&self.connection, "pg_dump", &self.connection.database).await?;
+        cmd.arg("--verbose");
 
         if let Some(ref options) = self.backup_options {
             for (key, value) in options {
@@ -85,6 +280,12 @@ impl BackupService for PostgresJob {
                     "data_only" if value == "true" => {
                         cmd.arg("--data-only");
                     }
+                    "format" if value == "directory" || value == "d" => {
+                        return Err(BusError::Config(
+                            "pg_dump directory format cannot be streamed; use plain, custom or tar"
+                                .into(),
+                        ));
+                    }
                     "format" => {
                         cmd.args(["--format", value]);
                     }
@@ -96,33 +297,109 @@ impl BackupService for PostgresJob {
             }
         }
 
-        let output = cmd.output().await?;
+        let mut artifact = self.pipeline.run(cmd, "pg_dump", &backup_file).await?;
+        artifact.server_version = self.server_version().await;
 
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("pg_dump failed for {}: {}", self.alias(), error_msg).into());
-        }
+        info!(
+            "PostgreSQL backup written with {} for {}: {}",
+            self.pipeline,
+            self.alias(),
+            artifact.path
+        );
 
-        let compressed_file = format!("{}.gz", backup_file);
-        let output = tokio::process::Command::new("gzip")
-            .arg(&backup_file)
-            .output()
-            .await?;
+        Ok(artifact)
+    }
 
-        if !output.status.success() {
-            warn!("Failed to compress PostgreSQL backup for {}", self.alias());
-            Ok(backup_file)
-        } else {
-            info!(
-                "PostgreSQL backup compressed for {}: {}",
-                self.alias(),
-                compressed_file
-            );
-            Ok(compressed_file)
+    async fn restore(&self, artifact: &Path, options: &RestoreOptions) -> Result<(), BusError> {
+        let database = options
+            .target_db
+            .as_deref()
+            .unwrap_or(&self.connection.database);
+        let clean = self
+            .restore_options
+            .as_ref()
+            .and_then(|options| options.get("clean"))
+            .is_some_and(|clean| clean == "true");
+
+        self.load(&self.connection, database, artifact, options, clean)
+            .await
+    }
+
+    /// Restores into a fresh scratch database, counts its tables and the rows of the listed
+    /// ones, runs the check query and drops the database again.
+    async fn verify(&self, artifact: &Path, options: &RestoreOptions) -> Option<Verification> {
+        let config = self.verify.as_ref()?;
+        let started = Instant::now();
+
+        let mut verification = Verification::default();
+        let checked = match self.connection.scratch(config, &self.alias) {
+            Ok((scratch, database)) => {
+                verification.database = database.clone();
+                let checked = self
+                    .check_restore(
+                        &scratch,
+                        &database,
+                        config,
+                        artifact,
+                        options,
+                        &mut verification,
+                    )
+                    .await;
+
+                let drop = format!("DROP DATABASE IF EXISTS {}", quote_identifier(&database));
+                if let Err(e) = query(&scratch, "postgres", &drop).await {
+                    warn!(
+                        error_kind = e.kind(),
+                        "Failed to drop scratch database '{}' of {}: {}",
+                        database,
+                        self.alias(),
+                        e
+                    );
+                }
+                checked
+            }
+            Err(e) => Err(e.to_string()),
+        };
+
+        verification.passed = checked.is_ok();
+        verification.error = checked.err();
+        verification.duration_seconds = started.elapsed().as_secs_f64();
+        Some(verification)
+    }
+
+    async fn health_check(&self) -> Result<String, BusError> {
+        let version = query(
+            &self.connection,
+            &self.connection.database,
+            "SHOW server_version",
+        )
+        .await?;
+        Ok(format!("PostgreSQL {}", version))
+    }
+
+    fn describe(&self) -> Capabilities {
+        Capabilities {
+            restore: true,
+            verify: self.verify.is_some(),
+            health_check: true,
         }
     }
 
     fn get_schedule(&self) -> &ScheduleConfig {
         &self.schedule
     }
+
+    fn is_retryable(&self, error: &BusError) -> bool {
+        match error {
+            // Retrying with the same credentials or database name can only fail again.
+            BusError::DumpTool { stderr, .. }
+                if stderr.contains("authentication failed")
+                    || stderr.contains("does not exist")
+                    || stderr.contains("permission denied") =>
+            {
+                false
+            }
+            _ => error.is_retryable(),
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{config::ServiceType, utils::deserialize_with_env};
+use crate::{config::ServiceType, error::BusError, secret::Secret};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RedisConnectionConfig {
@@ -8,16 +8,21 @@ pub struct RedisConnectionConfig {
     pub host: String,
     #[serde(default = "default_redis_port")]
     pub port: u16,
-    #[serde(deserialize_with = "deserialize_with_env")]
-    pub password: String,
+    /// Empty when the server doesn't require authentication.
+    pub password: Secret,
     pub cluster_mode: Option<bool>,
     pub sentinel_hosts: Option<Vec<String>>,
     pub master_name: Option<String>,
 }
 
 impl RedisConnectionConfig {
-    pub fn get_password(&self) -> String {
-        self.password.clone()
+    /// Resolves the password right before it is used; `None` without authentication.
+    pub async fn get_password(&self) -> Result<Option<String>, BusError> {
+        if self.password.is_empty() {
+            return Ok(None);
+        }
+
+        self.password.resolve().await.map(Some)
     }
 }
 
@@ -1,33 +1,113 @@
-use tracing::{info, warn};
+use std::path::Path;
+
+use tracing::info;
 
 use crate::{
-    common::BackupService,
+    common::{BackupService, Capabilities, RestoreOptions},
     config::{ScheduleConfig, ServiceConfig, ServiceType},
+    error::BusError,
+    pipeline::{Artifact, ArtifactPipeline, decode_artifact},
     service::redis::config::RedisConnectionConfig,
 };
 
 pub mod config;
 
+/// `backup_options` keys selecting how the RDB snapshot is taken.
+pub const BACKUP_OPTIONS: [&str; 1] = ["method"];
+
+/// `restore_options` keys: Redis only loads an RDB file on startup, so a restore stops the
+/// server, replaces the file it loads from and starts it again.
+pub const RESTORE_OPTIONS: [&str; 3] = ["rdb_path", "stop_command", "start_command"];
+
 pub struct RedisJob {
     service_type: ServiceType,
     alias: String,
     schedule: ScheduleConfig,
     connection: RedisConnectionConfig,
     backup_options: Option<std::collections::HashMap<String, String>>,
-    backup_dir: String,
+    restore_options: Option<std::collections::HashMap<String, String>>,
+    pipeline: ArtifactPipeline,
 }
 
 impl RedisJob {
-    pub fn new(config: ServiceConfig, backup_dir: String) -> Self {
+    pub fn new(config: ServiceConfig, pipeline: ArtifactPipeline) -> Self {
         Self {
-            service_type: ServiceType::Postgres,
+            service_type: ServiceType::Redis,
             alias: config.alias,
             schedule: config.schedule,
             connection: config.connection.as_redis().unwrap().clone(),
             backup_options: config.backup_options,
-            backup_dir,
+            restore_options: config.restore_options,
+            pipeline,
+        }
+    }
+
+    async fn connect(&self, password: Option<&str>) -> Result<redis::aio::Connection, BusError> {
+        let redis_url = format!("redis://{}:{}", self.connection.host, self.connection.port);
+
+        let client =
+            redis::Client::open(redis_url).map_err(|e| BusError::connection(self.alias(), e))?;
+        let mut con = client
+            .get_async_connection()
+            .await
+            .map_err(|e| BusError::connection(self.alias(), e))?;
+
+        if let Some(password) = password {
+            let _: () = redis::cmd("AUTH")
+                .arg(password)
+                .query_async(&mut con)
+                .await
+                .map_err(|e| BusError::connection(self.alias(), e))?;
         }
+
+        Ok(con)
+    }
+
+    fn restore_option(&self, key: &str) -> Result<&str, BusError> {
+        self.restore_options
+            .as_ref()
+            .and_then(|options| options.get(key))
+            .map(String::as_str)
+            .ok_or_else(|| {
+                BusError::Config(format!(
+                    "Restoring '{}' needs restore_options {}",
+                    self.alias,
+                    RESTORE_OPTIONS.join(", ")
+                ))
+            })
+    }
+}
+
+/// Best effort; INFO may be disabled through rename-command.
+async fn server_version(con: &mut redis::aio::Connection) -> Option<String> {
+    redis::cmd("INFO")
+        .arg("server")
+        .query_async::<_, String>(con)
+        .await
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("redis_version:"))
+                .map(|version| version.trim().to_string())
+        })
+}
+
+/// Runs a configured `stop_command` or `start_command` through the shell.
+async fn run_procedure(name: &str, command: &str) -> Result<(), BusError> {
+    info!("Running {}: {}", name, command);
+
+    let output = tokio::process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|e| BusError::tool_unavailable(name, e))?;
+
+    if !output.status.success() {
+        return Err(BusError::tool_failed(name, &output));
     }
+
+    Ok(())
 }
 
 #[async_trait::async_trait]
@@ -40,38 +120,21 @@ impl BackupService for RedisJob {
         &self.alias
     }
 
-    fn backup_dir(&self) -> &str {
-        &self.backup_dir
+    fn backup_options(&self) -> Option<&std::collections::HashMap<String, String>> {
+        self.backup_options.as_ref()
     }
 
-    async fn backup(
-        &self,
-        timestamp: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let backup_file = format!(
-            "{}/redis_{}_{}.rdb",
-            self.backup_dir(),
-            self.alias(),
-            timestamp
-        );
+    async fn backup(&self, timestamp: &str) -> Result<Artifact, BusError> {
+        let backup_file = format!("redis_{}_{}.rdb", self.alias(), timestamp);
 
         info!(
             "Creating Redis backup for {}: {}",
             self.alias(),
-            backup_file,
+            self.pipeline.artifact_path(&backup_file),
         );
 
-        let redis_url = format!("redis://{}:{}", self.connection.host, self.connection.port);
-
-        let client = redis::Client::open(redis_url)?;
-        let mut con = client.get_async_connection().await?;
-
-        if !self.connection.get_password().is_empty() {
-            let _: () = redis::cmd("AUTH")
-                .arg(&self.connection.get_password())
-                .query_async(&mut con)
-                .await?;
-        }
+        let password = self.connection.get_password().await?;
+        let mut con = self.connect(password.as_deref()).await?;
 
         let backup_method = self
             .backup_options
@@ -80,7 +143,7 @@ impl BackupService for RedisJob {
             .map(|s| s.as_str())
             .unwrap_or("rdb");
 
-        match backup_method {
+        let (cmd, tool) = match backup_method {
             "rdb" => {
                 let mut cmd = tokio::process::Command::new("redis-cli");
                 cmd.args([
@@ -90,63 +153,103 @@ impl BackupService for RedisJob {
                     &self.connection.port.to_string(),
                 ]);
 
-                if !self.connection.get_password().is_empty() {
-                    cmd.args(["-a", &self.connection.get_password()]);
+                // Passed through the environment; arguments are visible to anyone running `ps`.
+                if let Some(password) = &password {
+                    cmd.env("REDISCLI_AUTH", password);
                 }
 
-                cmd.args(["--rdb", &backup_file]);
-
-                let output = cmd.output().await?;
+                // "-" makes redis-cli stream the RDB payload to stdout.
+                cmd.args(["--rdb", "-"]);
 
-                if !output.status.success() {
-                    let error_msg = String::from_utf8_lossy(&output.stderr);
-                    return Err(format!(
-                        "Redis RDB backup failed for {}: {}",
-                        self.alias(),
-                        error_msg
-                    )
-                    .into());
-                }
+                (cmd, "redis-cli")
             }
             "save" => {
-                let _: String = redis::cmd("SAVE").query_async(&mut con).await?;
-
-                let output = tokio::process::Command::new("docker")
-                    .args([
-                        "cp",
-                        &format!("{}:/data/dump.rdb", self.connection.host),
-                        &backup_file,
-                    ])
-                    .output()
-                    .await?;
-
-                if !output.status.success() {
-                    return Err(
-                        format!("Failed to copy Redis dump file for {}", self.alias()).into(),
-                    );
-                }
+                let _: String = redis::cmd("SAVE")
+                    .query_async(&mut con)
+                    .await
+                    .map_err(|e| BusError::connection(self.alias(), e))?;
+
+                let mut cmd = tokio::process::Command::new("docker");
+                cmd.args(["exec", &self.connection.host, "cat", "/data/dump.rdb"]);
+
+                (cmd, "docker exec")
             }
             _ => {
-                return Err(format!("Unknown backup method: {}", backup_method).into());
+                return Err(BusError::Config(format!(
+                    "Unknown backup method: {}",
+                    backup_method
+                )));
             }
+        };
+
+        let mut artifact = self.pipeline.run(cmd, tool, &backup_file).await?;
+        artifact.server_version = server_version(&mut con).await;
+
+        info!(
+            "Redis backup written with {} for {}: {}",
+            self.pipeline,
+            self.alias(),
+            artifact.path
+        );
+
+        Ok(artifact)
+    }
+
+    async fn restore(&self, artifact: &Path, options: &RestoreOptions) -> Result<(), BusError> {
+        if options.target_db.is_some() {
+            return Err(BusError::Config(
+                "Redis restores replace the server's RDB file; --target-db does not apply".into(),
+            ));
         }
 
-        let compressed_file = format!("{}.gz", backup_file);
-        let output = tokio::process::Command::new("gzip")
-            .arg(&backup_file)
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            warn!("Failed to compress Redis backup for {}", self.alias());
-            Ok(backup_file)
-        } else {
-            info!(
-                "Redis backup compressed for {}: {}",
-                self.alias(),
-                compressed_file
-            );
-            Ok(compressed_file)
+        let rdb_path = self.restore_option("rdb_path")?;
+        let stop_command = self.restore_option("stop_command")?;
+        let start_command = self.restore_option("start_command")?;
+
+        // Decoded before stopping the server, so a bad artifact costs no downtime.
+        let partial = format!("{}.partial", rdb_path);
+        let decoded = decode_artifact(artifact, Some(Path::new(&partial)), options.keys.as_ref())
+            .await
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&partial);
+            })?;
+
+        run_procedure("stop_command", stop_command).await?;
+        tokio::fs::rename(&partial, rdb_path)
+            .await
+            .map_err(|e| BusError::storage(rdb_path, e))?;
+        run_procedure("start_command", start_command).await?;
+
+        info!(
+            "Restored {} ({} bytes) into {} of {}",
+            artifact.display(),
+            decoded,
+            rdb_path,
+            self.alias()
+        );
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<String, BusError> {
+        let password = self.connection.get_password().await?;
+        let mut con = self.connect(password.as_deref()).await?;
+
+        let _: String = redis::cmd("PING")
+            .query_async(&mut con)
+            .await
+            .map_err(|e| BusError::connection(self.alias(), e))?;
+
+        Ok(match server_version(&mut con).await {
+            Some(version) => format!("Redis {}", version),
+            None => "Redis".to_string(),
+        })
+    }
+
+    fn describe(&self) -> Capabilities {
+        Capabilities {
+            restore: true,
+            verify: false,
+            health_check: true,
         }
     }
 
@@ -0,0 +1,123 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BusError,
+    storage::{StorageBackend, StoredObject},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalConfig {
+    pub path: String,
+}
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(config: &LocalConfig) -> Self {
+        Self {
+            root: PathBuf::from(&config.path),
+        }
+    }
+
+    fn object(name: &str, metadata: std::fs::Metadata) -> StoredObject {
+        StoredObject {
+            name: name.to_string(),
+            size: metadata.len(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&self, source: &Path, name: &str) -> Result<(), BusError> {
+        tokio::fs::create_dir_all(&self.root)
+            .await
+            .map_err(|e| BusError::storage(&self.root, e))?;
+
+        let target = self.root.join(name);
+        let partial = self.root.join(format!("{}.partial", name));
+
+        // A hard link avoids copying the artifact when staging and destination share a filesystem.
+        if tokio::fs::hard_link(source, &partial).await.is_err() {
+            tokio::fs::copy(source, &partial)
+                .await
+                .map_err(|e| BusError::storage(&partial, e))?;
+        }
+
+        tokio::fs::rename(&partial, &target)
+            .await
+            .map_err(|e| BusError::storage(&target, e))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, BusError> {
+        let list_error = |e| BusError::storage(&self.root, e);
+
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(list_error(e)),
+        };
+
+        let mut objects = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(list_error)? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !name.starts_with(prefix) || name.ends_with(".partial") {
+                continue;
+            }
+
+            let metadata = entry.metadata().await.map_err(list_error)?;
+            if metadata.is_file() {
+                objects.push(Self::object(&name, metadata));
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), BusError> {
+        let path = self.root.join(name);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| BusError::storage(&path, e))
+    }
+
+    async fn get(&self, name: &str, target: &Path) -> Result<(), BusError> {
+        let path = self.root.join(name);
+        tokio::fs::copy(&path, target)
+            .await
+            .map_err(|e| BusError::storage(&path, e))?;
+
+        Ok(())
+    }
+
+    async fn read(&self, name: &str) -> Result<Vec<u8>, BusError> {
+        let path = self.root.join(name);
+        tokio::fs::read(&path)
+            .await
+            .map_err(|e| BusError::storage(&path, e))
+    }
+
+    async fn stat(&self, name: &str) -> Result<StoredObject, BusError> {
+        let path = self.root.join(name);
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| BusError::storage(&path, e))?;
+
+        Ok(Self::object(name, metadata))
+    }
+}
+
+impl Display for LocalStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.root.display())
+    }
+}
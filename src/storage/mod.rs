@@ -0,0 +1,157 @@
+use std::{fmt::Display, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{CommonConfig, RetentionConfig},
+    error::BusError,
+    retention::RetentionPolicy,
+    service::STAGING_DIR,
+    storage::{
+        local::{LocalConfig, LocalStorage},
+        s3::{S3Config, S3Storage},
+        sftp::{SftpConfig, SftpStorage},
+    },
+};
+
+pub mod local;
+pub mod s3;
+pub mod sftp;
+
+/// An artifact as seen by a storage backend; `name` is relative to the backend's root.
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Somewhere finished artifacts end up. Services only ever write to the local staging
+/// directory; the scheduler hands each finished artifact to every destination.
+#[async_trait::async_trait]
+pub trait StorageBackend: Send + Sync + Display {
+    /// Stores the local file at `source` as `name`.
+    async fn put(&self, source: &Path, name: &str) -> Result<(), BusError>;
+
+    /// Lists stored artifacts whose name starts with `prefix`.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, BusError>;
+
+    async fn delete(&self, name: &str) -> Result<(), BusError>;
+
+    /// Copies the stored artifact `name` to the local file `target`.
+    async fn get(&self, name: &str, target: &Path) -> Result<(), BusError>;
+
+    /// Reads a small stored object, such as a manifest, into memory.
+    async fn read(&self, name: &str) -> Result<Vec<u8>, BusError>;
+
+    async fn stat(&self, name: &str) -> Result<StoredObject, BusError>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    Local(LocalConfig),
+    S3(S3Config),
+    Sftp(SftpConfig),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DestinationConfig {
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    /// Days artifacts are kept in this destination; takes precedence over the service's
+    /// retention policy.
+    pub retention_days: Option<i64>,
+}
+
+pub struct Destination {
+    pub backend: Box<dyn StorageBackend>,
+    pub retention: RetentionPolicy,
+}
+
+impl StorageConfig {
+    pub fn create_backend(&self) -> Result<Box<dyn StorageBackend>, BusError> {
+        match self {
+            StorageConfig::Local(config) => Ok(Box::new(LocalStorage::new(config))),
+            StorageConfig::S3(config) => Ok(Box::new(S3Storage::new(config)?)),
+            StorageConfig::Sftp(config) => Ok(Box::new(SftpStorage::new(config)?)),
+        }
+    }
+}
+
+impl Destination {
+    /// A service's own `destinations` replace `common.destinations`; without either,
+    /// artifacts are kept in `common.backup_dir` as before.
+    pub fn resolve(
+        common: &CommonConfig,
+        service: Option<&Vec<DestinationConfig>>,
+        retention: Option<&RetentionConfig>,
+    ) -> Result<Vec<Self>, BusError> {
+        let default_retention = RetentionPolicy::resolve(common, retention)?;
+
+        let configs = match service.or(common.destinations.as_ref()) {
+            Some(configs) if configs.is_empty() => {
+                return Err(BusError::Config(
+                    "'destinations' must list at least one destination".into(),
+                ));
+            }
+            Some(configs) => configs.clone(),
+            None => vec![DestinationConfig {
+                storage: StorageConfig::Local(LocalConfig {
+                    path: common.backup_dir.clone(),
+                }),
+                retention_days: None,
+            }],
+        };
+
+        configs
+            .iter()
+            .map(|config| {
+                Ok(Destination {
+                    backend: config.storage.create_backend()?,
+                    retention: match config.retention_days {
+                        Some(days) => RetentionPolicy::MaxAge(days),
+                        None => default_retention.clone(),
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+impl Destination {
+    /// `backup_dir/.staging`, where backups wait until every destination has them; it is
+    /// pruned like a destination under the service's retention policy.
+    pub fn staging(
+        common: &CommonConfig,
+        retention: Option<&RetentionConfig>,
+    ) -> Result<Self, BusError> {
+        Ok(Destination {
+            backend: Box::new(LocalStorage::new(&LocalConfig {
+                path: Path::new(&common.backup_dir)
+                    .join(STAGING_DIR)
+                    .display()
+                    .to_string(),
+            })),
+            retention: RetentionPolicy::resolve(common, retention)?,
+        })
+    }
+}
+
+/// Copies `name` from the first destination that has it to the local file `target`.
+pub async fn fetch(
+    destinations: &[Destination],
+    name: &str,
+    target: &Path,
+) -> Result<(), BusError> {
+    let mut last_error = None;
+
+    for destination in destinations {
+        match destination.backend.stat(name).await {
+            Ok(_) => return destination.backend.get(name, target).await,
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| BusError::Config(format!("No destination to fetch '{}' from", name))))
+}
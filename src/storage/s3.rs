@@ -0,0 +1,211 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use s3::{Bucket, Region, creds::Credentials};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BusError,
+    secret::Secret,
+    storage::{StorageBackend, StoredObject},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    /// Custom endpoint for S3-compatible stores such as MinIO; AWS is used when unset.
+    pub endpoint: Option<String>,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Key prefix artifacts are stored under, e.g. `bus/production`.
+    pub prefix: Option<String>,
+    pub access_key: Secret,
+    pub secret_key: Secret,
+    /// Address buckets as `endpoint/bucket` instead of `bucket.endpoint`; defaults to on for custom endpoints.
+    pub path_style: Option<bool>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// How long resolved credentials are reused before they are resolved again.
+const CREDENTIALS_TTL: Duration = Duration::from_secs(60);
+
+pub struct S3Storage {
+    config: S3Config,
+    region: Region,
+    prefix: String,
+    bucket: Mutex<Option<(Box<Bucket>, Instant)>>,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<Self, BusError> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.trim_end_matches('/').to_string(),
+            },
+            None => config.region.parse().map_err(|e| {
+                BusError::Config(format!("Invalid S3 region '{}': {}", config.region, e))
+            })?,
+        };
+
+        let prefix = match config.prefix.as_deref().map(|p| p.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => format!("{}/", prefix),
+            _ => String::new(),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            region,
+            prefix,
+            bucket: Mutex::new(None),
+        })
+    }
+
+    /// The bucket, with credentials resolved at most once a minute so a batch of requests
+    /// shares them while rotated keys are still picked up.
+    pub(crate) async fn bucket(&self) -> Result<Box<Bucket>, BusError> {
+        if let Some((bucket, resolved)) = self.bucket.lock().unwrap().as_ref()
+            && resolved.elapsed() < CREDENTIALS_TTL
+        {
+            return Ok(bucket.clone());
+        }
+
+        let credentials = Credentials::new(
+            Some(&self.config.access_key.resolve().await?),
+            Some(&self.config.secret_key.resolve().await?),
+            None,
+            None,
+            None,
+        )
+        .map_err(|e| BusError::Config(format!("Invalid S3 credentials: {}", e)))?;
+
+        let mut bucket = Bucket::new(&self.config.bucket, self.region.clone(), credentials)
+            .map_err(|e| {
+                BusError::Config(format!("Invalid S3 bucket '{}': {}", self.config.bucket, e))
+            })?;
+        if self
+            .config
+            .path_style
+            .unwrap_or(self.config.endpoint.is_some())
+        {
+            bucket = bucket.with_path_style();
+        }
+
+        *self.bucket.lock().unwrap() = Some((bucket.clone(), Instant::now()));
+        Ok(bucket)
+    }
+
+    /// Object key of the artifact `name`, under the configured prefix.
+    pub(crate) fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// Artifact name of an object key returned by a listing; the reverse of [`Self::key`].
+    pub(crate) fn name<'a>(&self, key: &'a str) -> &'a str {
+        key.strip_prefix(&self.prefix).unwrap_or(key)
+    }
+
+    fn error(&self, error: impl Display) -> BusError {
+        // The credentials may have been rotated; resolve them again for the next request.
+        self.bucket.lock().unwrap().take();
+        BusError::remote(self, error)
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for S3Storage {
+    async fn put(&self, source: &Path, name: &str) -> Result<(), BusError> {
+        let mut file = tokio::fs::File::open(source)
+            .await
+            .map_err(|e| BusError::storage(source, e))?;
+
+        // Streams the file, switching to a multipart upload once it exceeds one part.
+        self.bucket()
+            .await?
+            .put_object_stream(&mut file, self.key(name))
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, BusError> {
+        let pages = self
+            .bucket()
+            .await?
+            .list(self.key(prefix), None)
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| StoredObject {
+                name: self.name(&object.key).to_string(),
+                size: object.size,
+            })
+            .collect())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), BusError> {
+        self.bucket()
+            .await?
+            .delete_object(self.key(name))
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, name: &str, target: &Path) -> Result<(), BusError> {
+        let mut file = tokio::fs::File::create(target)
+            .await
+            .map_err(|e| BusError::storage(target, e))?;
+
+        self.bucket()
+            .await?
+            .get_object_to_writer(self.key(name), &mut file)
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(())
+    }
+
+    async fn read(&self, name: &str) -> Result<Vec<u8>, BusError> {
+        let response = self
+            .bucket()
+            .await?
+            .get_object(self.key(name))
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(response.to_vec())
+    }
+
+    async fn stat(&self, name: &str) -> Result<StoredObject, BusError> {
+        let (head, _) = self
+            .bucket()
+            .await?
+            .head_object(self.key(name))
+            .await
+            .map_err(|e| self.error(e))?;
+
+        Ok(StoredObject {
+            name: name.to_string(),
+            size: head.content_length.unwrap_or_default().max(0) as u64,
+        })
+    }
+}
+
+impl Display for S3Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "s3://{}/{}", self.config.bucket, self.prefix)
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+
+use crate::{
+    error::BusError,
+    secret::Secret,
+    storage::{StorageBackend, StoredObject},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "default_sftp_port")]
+    pub port: u16,
+    pub username: String,
+    /// Private key file; takes precedence over `password`.
+    pub private_key: Option<String>,
+    /// Passphrase protecting `private_key`.
+    pub passphrase: Option<Secret>,
+    pub password: Option<Secret>,
+    /// Remote directory artifacts are stored in.
+    pub path: String,
+    /// OpenSSH known_hosts file the server key is checked against; defaults to `~/.ssh/known_hosts`.
+    pub known_hosts: Option<String>,
+}
+
+fn default_sftp_port() -> u16 {
+    22
+}
+
+/// How long an idle session is kept for the next operation.
+const SESSION_IDLE: Duration = Duration::from_secs(60);
+
+pub struct SftpStorage {
+    config: Arc<SftpConfig>,
+    known_hosts: PathBuf,
+    /// The last session and when it was last used, so back-to-back operations share it.
+    session: Mutex<Option<(Sftp, Instant)>>,
+}
+
+impl SftpStorage {
+    pub fn new(config: &SftpConfig) -> Result<Self, BusError> {
+        if config.private_key.is_none() && config.password.is_none() {
+            return Err(BusError::Config(format!(
+                "SFTP destination {}@{} needs a 'private_key' or a 'password'",
+                config.username, config.host
+            )));
+        }
+
+        let known_hosts = match &config.known_hosts {
+            Some(path) => PathBuf::from(path),
+            None => std::env::var("HOME")
+                .map(|home| Path::new(&home).join(".ssh/known_hosts"))
+                .map_err(|_| {
+                    BusError::Config(
+                        "SFTP destinations need 'known_hosts' when HOME is not set".into(),
+                    )
+                })?,
+        };
+
+        Ok(Self {
+            config: Arc::new(config.clone()),
+            known_hosts,
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Runs `operation` in a blocking task with the remote directory, on the session of
+    /// the previous operation if it was recent and succeeded, or on a new one.
+    async fn with_sftp<T, F>(&self, operation: F) -> Result<T, BusError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Sftp, &Path) -> io::Result<T> + Send + 'static,
+    {
+        let config = self.config.clone();
+        let known_hosts = self.known_hosts.clone();
+        let location = self.to_string();
+
+        let cached = self
+            .session
+            .lock()
+            .unwrap()
+            .take()
+            .filter(|(_, used)| used.elapsed() < SESSION_IDLE)
+            .map(|(sftp, _)| sftp);
+
+        // Resolved for every new session, so rotated credentials are picked up.
+        let credentials = match (cached.is_some(), &config.private_key, &config.password) {
+            (true, _, _) => None,
+            (false, Some(key), _) => Some(Credentials::PrivateKey {
+                key: key.clone(),
+                passphrase: match &config.passphrase {
+                    Some(passphrase) => Some(passphrase.resolve().await?),
+                    None => None,
+                },
+            }),
+            (false, None, Some(password)) => Some(Credentials::Password(password.resolve().await?)),
+            (false, None, None) => unreachable!("checked in SftpStorage::new"),
+        };
+
+        let (sftp, result) = tokio::task::spawn_blocking(move || {
+            let sftp = match (cached, credentials) {
+                (Some(sftp), _) => sftp,
+                (None, Some(credentials)) => {
+                    connect(&config, &credentials, &known_hosts, &location)?
+                }
+                (None, None) => unreachable!("credentials are resolved without a session"),
+            };
+            let result = operation(&sftp, Path::new(&config.path))
+                .map_err(|e| BusError::remote(&location, e));
+            Ok::<_, BusError>((sftp, result))
+        })
+        .await
+        .map_err(|e| BusError::remote(self, e))??;
+
+        // A failed operation may have left the session broken.
+        if result.is_ok() {
+            *self.session.lock().unwrap() = Some((sftp, Instant::now()));
+        }
+        result
+    }
+}
+
+enum Credentials {
+    PrivateKey {
+        key: String,
+        passphrase: Option<String>,
+    },
+    Password(String),
+}
+
+/// Opens an authenticated SFTP session, refusing servers whose key isn't in `known_hosts`.
+fn connect(
+    config: &SftpConfig,
+    credentials: &Credentials,
+    known_hosts: &Path,
+    location: &str,
+) -> Result<Sftp, BusError> {
+    let remote_error = |e: ssh2::Error| BusError::remote(location, e);
+
+    let tcp = TcpStream::connect((config.host.as_str(), config.port))
+        .map_err(|e| BusError::remote(location, e))?;
+    let mut session = Session::new().map_err(remote_error)?;
+    session.set_tcp_stream(tcp);
+    session.handshake().map_err(remote_error)?;
+
+    let mut hosts = session.known_hosts().map_err(remote_error)?;
+    hosts
+        .read_file(known_hosts, KnownHostFileKind::OpenSSH)
+        .map_err(|e| {
+            BusError::Config(format!(
+                "Failed to read known_hosts {}: {}",
+                known_hosts.display(),
+                e
+            ))
+        })?;
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| BusError::remote(location, "server sent no host key"))?;
+
+    match hosts.check_port(&config.host, config.port, key) {
+        CheckResult::Match => {}
+        CheckResult::Mismatch => {
+            return Err(BusError::Config(format!(
+                "Host key of {}:{} does not match {}",
+                config.host,
+                config.port,
+                known_hosts.display()
+            )));
+        }
+        CheckResult::NotFound | CheckResult::Failure => {
+            return Err(BusError::Config(format!(
+                "Host {}:{} is not in {}; add it with ssh-keyscan",
+                config.host,
+                config.port,
+                known_hosts.display()
+            )));
+        }
+    }
+
+    match credentials {
+        Credentials::PrivateKey { key, passphrase } => session.userauth_pubkey_file(
+            &config.username,
+            None,
+            Path::new(key),
+            passphrase.as_deref(),
+        ),
+        Credentials::Password(password) => session.userauth_password(&config.username, password),
+    }
+    // Retrying with the same credentials can only fail again.
+    .map_err(|e| BusError::Config(format!("Authentication to {} failed: {}", location, e)))?;
+
+    session.sftp().map_err(remote_error)
+}
+
+fn stored_object(name: &str, stat: &ssh2::FileStat) -> StoredObject {
+    StoredObject {
+        name: name.to_string(),
+        size: stat.size.unwrap_or_default(),
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for SftpStorage {
+    async fn put(&self, source: &Path, name: &str) -> Result<(), BusError> {
+        let mut file = File::open(source).map_err(|e| BusError::storage(source, e))?;
+        let name = name.to_string();
+
+        self.with_sftp(move |sftp, root| {
+            if sftp.stat(root).is_err() {
+                sftp.mkdir(root, 0o755)?;
+            }
+
+            let target = root.join(&name);
+            let partial = root.join(format!("{}.partial", name));
+
+            let mut remote = sftp.create(&partial)?;
+            io::copy(&mut file, &mut remote)?;
+            // Not every server supports the fsync extension.
+            let _ = remote.fsync();
+            drop(remote);
+
+            // SFTP v3 servers such as OpenSSH refuse to rename over an existing file.
+            if sftp.rename(&partial, &target, None).is_err() {
+                if sftp.stat(&target).is_ok() {
+                    sftp.unlink(&target)?;
+                }
+                sftp.rename(&partial, &target, None)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, BusError> {
+        let prefix = prefix.to_string();
+
+        self.with_sftp(move |sftp, root| {
+            let entries = match sftp.readdir(root) {
+                Ok(entries) => entries,
+                Err(e) if e.code() == ssh2::ErrorCode::SFTP(2) => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+
+            Ok(entries
+                .iter()
+                .filter(|(_, stat)| stat.is_file())
+                .filter_map(|(path, stat)| {
+                    let name = path.file_name()?.to_str()?;
+                    (name.starts_with(&prefix) && !name.ends_with(".partial"))
+                        .then(|| stored_object(name, stat))
+                })
+                .collect())
+        })
+        .await
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), BusError> {
+        let name = name.to_string();
+        self.with_sftp(move |sftp, root| Ok(sftp.unlink(&root.join(name))?))
+            .await
+    }
+
+    async fn get(&self, name: &str, target: &Path) -> Result<(), BusError> {
+        let mut file = File::create(target).map_err(|e| BusError::storage(target, e))?;
+        let name = name.to_string();
+
+        self.with_sftp(move |sftp, root| {
+            let mut remote = sftp.open(root.join(name))?;
+            io::copy(&mut remote, &mut file)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read(&self, name: &str) -> Result<Vec<u8>, BusError> {
+        let name = name.to_string();
+        self.with_sftp(move |sftp, root| {
+            let mut contents = Vec::new();
+            sftp.open(root.join(name))?.read_to_end(&mut contents)?;
+            Ok(contents)
+        })
+        .await
+    }
+
+    async fn stat(&self, name: &str) -> Result<StoredObject, BusError> {
+        let name = name.to_string();
+        self.with_sftp(move |sftp, root| {
+            let stat = sftp.stat(&root.join(&name))?;
+            Ok(stored_object(&name, &stat))
+        })
+        .await
+    }
+}
+
+impl Display for SftpStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sftp://{}@{}:{}/{}",
+            self.config.username,
+            self.config.host,
+            self.config.port,
+            self.config.path.trim_matches('/')
+        )
+    }
+}
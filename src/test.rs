@@ -1,6 +1,40 @@
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
-use crate::{config::Config, utils::substitute_env_vars};
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    commands,
+    common::{BackupService, Capabilities, RestoreOptions},
+    compression::{Codec, Compression, CompressionConfig},
+    config::{
+        Config, ConnectionConfig, QuotaConfig, RetentionConfig, RetryConfig, ScheduleConfig,
+        ServiceConfig, ServiceType,
+    },
+    encryption::{Encryption, EncryptionConfig},
+    error::BusError,
+    manifest::{CatalogEntry, MANIFEST_VERSION, Manifest, ManifestEntry, catalog},
+    pipeline::{ArtifactPipeline, feed, open_artifact, sha256_file},
+    retention::{Quota, RetentionPolicy, plan_cleanup, plan_total_quota},
+    scheduler::{BackupScheduler, retry::RetryPolicy, schedule::Schedule},
+    secret::Secret,
+    service::{ServiceFactory, postgres::config::PostgresConnectionConfig},
+    storage::{
+        Destination, DestinationConfig, StorageBackend, StorageConfig,
+        local::{LocalConfig, LocalStorage},
+        s3::{S3Config, S3Storage},
+        sftp::SftpConfig,
+    },
+    utils::substitute_env_vars,
+    validation,
+};
 
 #[test]
 fn test_env_var_substitution() {
@@ -28,11 +62,73 @@ fn test_env_var_substitution() {
 
     // Test error case
     let input_error = "password = \"${MISSING_VAR}\"";
-    assert!(substitute_env_vars(input_error).is_err());
+    assert!(matches!(
+        substitute_env_vars(input_error),
+        Err(BusError::Config(_))
+    ));
+
+    // `$$` is a literal `$` and never starts a variable
+    let input_escaped = "password = \"pa$$word$${TEST_VAR}\"";
+    let result = substitute_env_vars(input_escaped).unwrap();
+    assert_eq!(result, "password = \"pa$word${TEST_VAR}\"");
+
+    // Required variables fail with their message, even when set but empty
+    let input_required = "host = \"${EMPTY_VAR:?set it to the primary's address}\"";
+    assert_eq!(
+        substitute_env_vars(input_required).unwrap_err().to_string(),
+        "Invalid configuration: Environment variable 'EMPTY_VAR' is required: set it to the primary's address"
+    );
 }
 
-#[test]
-fn test_config_parsing_with_env_vars() {
+#[tokio::test]
+async fn test_env_vars_are_substituted_in_every_field() {
+    unsafe {
+        env::set_var("TEST_TEMPLATE_HOST", "db.internal");
+        env::set_var("TEST_TEMPLATE_DIR", "/tmp/bus-template");
+    }
+
+    let toml_content = r#"[common]
+backup_dir = "${TEST_TEMPLATE_DIR}/staging"
+
+[[services]]
+type = "postgres"
+alias = "${TEST_TEMPLATE_ALIAS:-main-db}"
+
+[services.connection]
+service_type = "postgres"
+host = "${TEST_TEMPLATE_HOST}"
+username = "postgres"
+password = "pa$$word"
+database = "${TEST_TEMPLATE_DATABASE:?the database to back up}"
+
+[services.schedule]
+interval_seconds = 3600
+"#;
+
+    let error = crate::parse_config(Path::new("bus.toml"), toml_content).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid configuration: 1 problem(s) in bus.toml:\n  bus.toml:13: services[0].connection.database: \
+         Environment variable 'TEST_TEMPLATE_DATABASE' is required: the database to back up"
+    );
+
+    unsafe {
+        env::set_var("TEST_TEMPLATE_DATABASE", "app");
+    }
+    let config = crate::parse_config(Path::new("bus.toml"), toml_content).unwrap();
+    let connection = config.services[0].connection.as_postgres().unwrap();
+    assert_eq!(config.common.backup_dir, "/tmp/bus-template/staging");
+    assert_eq!(config.services[0].alias, "main-db");
+    assert_eq!(connection.host, "db.internal");
+    assert_eq!(connection.database, "app");
+    assert_eq!(
+        pg_env(connection, "PGPASSWORD").await.as_deref(),
+        Some("pa$word")
+    );
+}
+
+#[tokio::test]
+async fn test_config_parsing_with_env_vars() {
     dotenvy::dotenv().ok();
 
     unsafe {
@@ -50,7 +146,9 @@ fn test_config_parsing_with_env_vars() {
             alias = "test-db"
 
             [services.connection]
+            service_type = "postgres"
             host = "localhost"
+            username = "postgres"
             password = "${TEST_DB_PASSWORD}"
             database = "${TEST_DB_NAME:-testdb}"
 
@@ -60,12 +158,1995 @@ fn test_config_parsing_with_env_vars() {
             start_time = "02:00"
         "#;
 
-    let substituted = substitute_env_vars(toml_content).unwrap();
-    let config: Config = toml::from_str(&substituted).unwrap();
+    let config = crate::parse_config(Path::new("bus.toml"), toml_content).unwrap();
+
+    let connection = config.services[0].connection.as_postgres().unwrap();
+    assert_eq!(
+        pg_env(connection, "PGPASSWORD").await.as_deref(),
+        Some("secret123")
+    );
+    assert_eq!(connection.database, "testdb");
+}
+
+/// The value of `name` a libpq tool connecting with `connection` is started with.
+async fn pg_env(connection: &PostgresConnectionConfig, name: &str) -> Option<String> {
+    let mut cmd = tokio::process::Command::new("pg_dump");
+    connection.authenticate(&mut cmd).await.unwrap();
+
+    cmd.as_std()
+        .get_envs()
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value)
+        .map(|value| value.to_string_lossy().into_owned())
+}
+
+#[tokio::test]
+async fn test_secret_references() {
+    let dir = env::temp_dir().join(format!("bus-secret-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("pg_password");
+    std::fs::write(&file, "from-file\n").unwrap();
+
+    let literal = Secret::from("plain");
+    assert_eq!(literal.resolve().await.unwrap(), "plain");
+    assert_eq!(format!("{:?}", literal), "Secret(***)");
+
+    let from_file = Secret::from(format!("file:{}", file.display()).as_str());
+    assert_eq!(from_file.resolve().await.unwrap(), "from-file");
+
+    // Files are read again on every use, so rotated secrets are picked up.
+    std::fs::write(&file, "rotated").unwrap();
+    assert_eq!(from_file.resolve().await.unwrap(), "rotated");
+
+    let from_command = Secret::from("cmd:printf 'helper\\nignored'");
+    assert_eq!(from_command.resolve().await.unwrap(), "helper");
+
+    let failing = Secret::from("cmd:echo denied >&2; exit 3");
+    let error = failing.resolve().await.unwrap_err();
+    assert!(matches!(error, BusError::Secret(_)));
+    assert!(error.to_string().contains("denied"));
+    assert!(error.is_retryable());
+
+    // Without a password, pg_dump falls back to the pgpass file.
+    let connection: PostgresConnectionConfig = toml::from_str(
+        r#"
+            service_type = "postgres"
+            host = "localhost"
+            username = "postgres"
+            database = "app"
+            passfile = "/run/secrets/pgpass"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(pg_env(&connection, "PGPASSWORD").await, None);
+    assert_eq!(
+        pg_env(&connection, "PGPASSFILE").await.as_deref(),
+        Some("/run/secrets/pgpass")
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_secrets_are_redacted_from_debug_display_and_serialize() {
+    let toml_content = r#"
+            [common]
+            backup_dir = "/tmp/backups"
+
+            [common.encryption]
+            method = "passphrase"
+            passphrase = "correct horse"
+
+            [[common.destinations]]
+            type = "s3"
+            bucket = "backups"
+            access_key = "AKIAEXAMPLE"
+            secret_key = "cmd:vault read -field=key token=s.hunter2"
+
+            [[services]]
+            type = "postgres"
+            alias = "main-db"
+
+            [services.connection]
+            service_type = "postgres"
+            host = "localhost"
+            username = "postgres"
+            password = "pg-hunter2"
+            database = "app"
+
+            [services.schedule]
+            interval_seconds = 3600
+
+            [[services]]
+            type = "redis"
+            alias = "cache"
+
+            [services.connection]
+            service_type = "redis"
+            host = "localhost"
+            password = "file:/run/secrets/redis_password"
+
+            [services.schedule]
+            interval_seconds = 3600
+        "#;
+
+    let config: Config = toml::from_str(toml_content).unwrap();
+
+    let outputs = [
+        format!("{:?}", config),
+        format!("{}", config.services[0]),
+        format!("{}", config.services[1]),
+        serde_json::to_string(&config).unwrap(),
+        toml::to_string(&config).unwrap(),
+    ];
+    for output in &outputs {
+        for secret in ["correct horse", "AKIAEXAMPLE", "hunter2"] {
+            assert!(!output.contains(secret), "{} leaked in {}", secret, output);
+        }
+    }
+
+    // Where a secret comes from is still visible.
+    assert!(outputs[2].contains("file:/run/secrets/redis_password"));
+    assert!(outputs[0].contains("cmd:***"));
+}
+
+#[test]
+fn test_destinations_resolution() {
+    unsafe {
+        env::set_var("TEST_S3_SECRET_KEY", "minio-secret");
+    }
+
+    let toml_content = r#"
+            [common]
+            backup_dir = "/tmp/backups"
+            retention_days = 2
+
+            [[common.destinations]]
+            type = "s3"
+            endpoint = "http://localhost:9000"
+            bucket = "backups"
+            prefix = "/bus/production/"
+            access_key = "minio"
+            secret_key = "${TEST_S3_SECRET_KEY}"
+            retention_days = 30
+
+            [[services]]
+            type = "redis"
+            alias = "cache"
+
+            [services.connection]
+            service_type = "redis"
+            host = "localhost"
+            password = ""
+
+            [services.schedule]
+            interval_seconds = 3600
+
+            [[services]]
+            type = "redis"
+            alias = "sessions"
+
+            [services.connection]
+            service_type = "redis"
+            host = "localhost"
+            password = ""
+
+            [services.schedule]
+            interval_seconds = 3600
+
+            [[services.destinations]]
+            type = "local"
+            path = "/var/backups/sessions"
+        "#;
+
+    let config: Config = toml::from_str(&substitute_env_vars(toml_content).unwrap()).unwrap();
+
+    let common = Destination::resolve(&config.common, None, None).unwrap();
+    assert_eq!(common.len(), 1);
+    assert_eq!(
+        common[0].backend.to_string(),
+        "s3://backups/bus/production/"
+    );
+    assert_eq!(common[0].retention, RetentionPolicy::MaxAge(30));
+
+    let sessions = Destination::resolve(
+        &config.common,
+        config.services[1].destinations.as_ref(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].backend.to_string(), "/var/backups/sessions");
+    assert_eq!(sessions[0].retention, RetentionPolicy::MaxAge(2));
+
+    // Without any destinations, backups stay in backup_dir.
+    let mut legacy = config.common.clone();
+    legacy.destinations = None;
+    let fallback = Destination::resolve(&legacy, None, None).unwrap();
+    assert_eq!(fallback[0].backend.to_string(), "/tmp/backups");
+}
+
+#[test]
+fn test_s3_keys_use_the_prefix() {
+    let config = |prefix: Option<&str>| S3Config {
+        bucket: "backups".to_string(),
+        endpoint: Some("http://localhost:9000/".to_string()),
+        region: "us-east-1".to_string(),
+        prefix: prefix.map(str::to_string),
+        access_key: Secret::from("minio"),
+        secret_key: Secret::from("minio-secret"),
+        path_style: None,
+    };
+
+    let storage = S3Storage::new(&config(Some("/bus/production/"))).unwrap();
+    assert_eq!(storage.to_string(), "s3://backups/bus/production/");
+    assert_eq!(
+        storage.key("postgres_app_2024.sql.gz"),
+        "bus/production/postgres_app_2024.sql.gz"
+    );
+    assert_eq!(
+        storage.name("bus/production/postgres_app_2024.sql.gz"),
+        "postgres_app_2024.sql.gz"
+    );
+
+    // An empty prefix stores artifacts at the root of the bucket.
+    for prefix in [None, Some(""), Some("/")] {
+        let storage = S3Storage::new(&config(prefix)).unwrap();
+        assert_eq!(storage.to_string(), "s3://backups/");
+        assert_eq!(storage.key("redis_cache.rdb"), "redis_cache.rdb");
+        assert_eq!(storage.name("redis_cache.rdb"), "redis_cache.rdb");
+    }
+}
+
+#[tokio::test]
+async fn test_s3_credentials_are_resolved_once_per_batch() {
+    let counter = env::temp_dir().join(format!("bus-s3-resolved-{}", std::process::id()));
+    let storage = S3Storage::new(&S3Config {
+        bucket: "backups".to_string(),
+        endpoint: Some("http://localhost:9000".to_string()),
+        region: "us-east-1".to_string(),
+        prefix: None,
+        access_key: Secret::from(
+            format!("cmd:echo x >> {}; echo minio", counter.display()).as_str(),
+        ),
+        secret_key: Secret::from("minio-secret"),
+        path_style: None,
+    })
+    .unwrap();
+
+    for _ in 0..3 {
+        storage.bucket().await.unwrap();
+    }
+    assert_eq!(
+        std::fs::read_to_string(&counter).unwrap().lines().count(),
+        1
+    );
+
+    std::fs::remove_file(&counter).unwrap();
+}
+
+#[test]
+fn test_sftp_destination_config() {
+    unsafe {
+        env::set_var("TEST_SFTP_PASSWORD", "sftp-secret");
+    }
+
+    let destination: DestinationConfig = toml::from_str(
+        &substitute_env_vars(
+            r#"
+            type = "sftp"
+            host = "backup.example.com"
+            username = "bus"
+            password = "${TEST_SFTP_PASSWORD}"
+            path = "/srv/backups/"
+            known_hosts = "/etc/bus/known_hosts"
+        "#,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    let StorageConfig::Sftp(sftp) = &destination.storage else {
+        panic!("expected an sftp destination");
+    };
+    assert_eq!(sftp.port, 22);
+    assert_eq!(sftp.password, Some(Secret::from("sftp-secret")));
+    assert_eq!(
+        destination.storage.create_backend().unwrap().to_string(),
+        "sftp://bus@backup.example.com:22/srv/backups"
+    );
+
+    let mut without_credentials = sftp.clone();
+    without_credentials.password = None;
+    assert!(matches!(
+        StorageConfig::Sftp(without_credentials).create_backend(),
+        Err(BusError::Config(_))
+    ));
+}
+
+/// Needs an SFTP server, given by `BUS_TEST_SFTP_HOST`, `_PORT`, `_USER`, `_PASSWORD` and
+/// `_KNOWN_HOSTS`; run with `cargo test -- --ignored`.
+#[tokio::test]
+#[ignore]
+async fn test_sftp_put_replaces_an_existing_artifact() {
+    let var = |name: &str| env::var(format!("BUS_TEST_SFTP_{}", name)).unwrap();
+    let storage = StorageConfig::Sftp(SftpConfig {
+        host: var("HOST"),
+        port: var("PORT").parse().unwrap(),
+        username: var("USER"),
+        private_key: None,
+        passphrase: None,
+        password: Some(Secret::from(var("PASSWORD").as_str())),
+        path: format!("/bus-test-{}", std::process::id()),
+        known_hosts: Some(var("KNOWN_HOSTS")),
+    })
+    .create_backend()
+    .unwrap();
+
+    let source = env::temp_dir().join(format!("bus-sftp-source-{}", std::process::id()));
+    let name = "redis_cache_2024-06-07.rdb";
+
+    // A redelivered artifact replaces the copy an earlier attempt left behind.
+    std::fs::write(&source, "first").unwrap();
+    storage.put(&source, name).await.unwrap();
+    std::fs::write(&source, "second try").unwrap();
+    storage.put(&source, name).await.unwrap();
+
+    assert_eq!(storage.read(name).await.unwrap(), b"second try");
+    let listed = storage.list("redis_cache_").await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].size, 10);
+
+    storage.delete(name).await.unwrap();
+    std::fs::remove_file(&source).unwrap();
+}
+
+#[tokio::test]
+async fn test_local_storage_backend() {
+    let dir = env::temp_dir().join(format!("bus-local-storage-{}", std::process::id()));
+    let source = env::temp_dir().join(format!("bus-local-source-{}.sql", std::process::id()));
+    std::fs::write(&source, "SELECT 1;\n").unwrap();
+
+    let storage = LocalStorage::new(&LocalConfig {
+        path: dir.to_str().unwrap().to_string(),
+    });
+
+    let name = "postgres_db_2024-06-07_02:00:00.000000000.sql";
+    storage.put(&source, name).await.unwrap();
+    storage
+        .put(&source, "postgres_other_2024-06-07.sql")
+        .await
+        .unwrap();
+
+    let listed = storage.list("postgres_db_").await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].name, name);
+    assert_eq!(storage.stat(name).await.unwrap().size, 10);
+
+    let restored = dir.join("restored.sql");
+    storage.get(name, &restored).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&restored).unwrap(), "SELECT 1;\n");
+
+    storage.delete(name).await.unwrap();
+    assert!(storage.list("postgres_db_").await.unwrap().is_empty());
+    assert!(storage.stat(name).await.is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(&source).unwrap();
+}
+
+/// Writes a 4 byte artifact and its manifest into `dir`, as the scheduler stores them.
+fn write_stored_backup(dir: &std::path::Path, alias: &str, artifact: &str, finished_at: &str) {
+    std::fs::write(dir.join(artifact), "dump").unwrap();
+    std::fs::write(
+        dir.join(format!("{}.manifest.json", artifact)),
+        format!(
+            r#"{{"version": 1, "alias": "{}", "service_type": "postgres", "artifact": "{}", "finished_at": "{}", "size": 4, "sha256": "b6ca0868bca6a2926b70aa1a71592038d9030fe26d4214edcfbd6cf41f2f4654"}}"#,
+            alias, artifact, finished_at
+        ),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_catalog_only_lists_artifacts_of_the_exact_service() {
+    let dir = env::temp_dir().join(format!("bus-catalog-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let storage = LocalStorage::new(&LocalConfig {
+        path: dir.to_str().unwrap().to_string(),
+    });
+
+    let write_manifest = |alias: &str, artifact: &str, finished_at: &str| {
+        write_stored_backup(&dir, alias, artifact, finished_at)
+    };
+    write_manifest(
+        "db",
+        "postgres_db_2024-06-08_02:00:00.sql",
+        "2024-06-08T02:00:05Z",
+    );
+    write_manifest(
+        "db",
+        "postgres_db_2024-06-07_02:00:00.sql",
+        "2024-06-07T02:00:05Z",
+    );
+    // Shares the `postgres_db_` prefix but belongs to another service.
+    write_manifest(
+        "db_replica",
+        "postgres_db_replica_2024-06-07_02:00:00.sql",
+        "2024-06-07T02:00:05Z",
+    );
+    // Not written by Bus: no manifest.
+    std::fs::write(dir.join("postgres_db_2024-01-01_00:00:00.sql"), "dump").unwrap();
+    std::fs::write(dir.join("postgres_db_broken.sql.manifest.json"), "{").unwrap();
+
+    let entries = catalog(&storage, &ServiceType::Postgres, "db")
+        .await
+        .unwrap();
+    let artifacts: Vec<_> = entries
+        .iter()
+        .map(|e| e.manifest.artifact.as_str())
+        .collect();
+    assert_eq!(
+        artifacts,
+        [
+            "postgres_db_2024-06-07_02:00:00.sql",
+            "postgres_db_2024-06-08_02:00:00.sql"
+        ]
+    );
+    assert!(
+        catalog(&storage, &ServiceType::Redis, "db")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn catalog_entries(
+    first: chrono::DateTime<Utc>,
+    step: chrono::Duration,
+    count: i32,
+) -> Vec<CatalogEntry> {
+    (0..count)
+        .map(|i| {
+            let finished_at = first + step * i;
+            let artifact = format!(
+                "postgres_db_{}.sql",
+                finished_at.format("%Y-%m-%d_%H:%M:%S")
+            );
+            CatalogEntry {
+                manifest_name: format!("{}.manifest.json", artifact),
+                manifest: ManifestEntry {
+                    alias: "db".to_string(),
+                    service_type: ServiceType::Postgres,
+                    artifact,
+                    finished_at,
+                    size: 1,
+                    sha256: String::new(),
+                    backup_options: Default::default(),
+                },
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_gfs_retention_policy() {
+    let first = Utc.with_ymd_and_hms(2024, 1, 1, 2, 0, 0).unwrap();
+    let now = first + chrono::Duration::days(400);
+
+    // 7 dailies, 4 weeklies and 12 monthlies out of 400 daily backups.
+    let daily = catalog_entries(first, chrono::Duration::days(1), 400);
+    let policy = RetentionPolicy::from_config(&RetentionConfig {
+        keep_daily: 7,
+        keep_weekly: 4,
+        keep_monthly: 12,
+        ..Default::default()
+    })
+    .unwrap();
+    let expired = policy.expired(&daily, now);
+    let kept: Vec<_> = daily
+        .iter()
+        .filter(|entry| {
+            !expired
+                .iter()
+                .any(|e| e.manifest_name == entry.manifest_name)
+        })
+        .map(|entry| entry.manifest.finished_at)
+        .collect();
+
+    assert!(kept.len() <= 7 + 4 + 12);
+    assert!(
+        kept.ends_with(
+            &daily[393..]
+                .iter()
+                .map(|e| e.manifest.finished_at)
+                .collect::<Vec<_>>()
+        )
+    );
+    // The last backup of each month is kept for a year.
+    assert!(kept.contains(&Utc.with_ymd_and_hms(2024, 3, 31, 2, 0, 0).unwrap()));
+    assert!(!kept.contains(&Utc.with_ymd_and_hms(2024, 3, 30, 2, 0, 0).unwrap()));
+    assert!(
+        kept.iter()
+            .all(|t| *t > Utc.with_ymd_and_hms(2024, 1, 31, 2, 0, 0).unwrap())
+    );
+
+    // 24 hourlies out of three days of hourly backups.
+    let hourly = catalog_entries(first, chrono::Duration::hours(1), 72);
+    let policy = RetentionPolicy::from_config(&RetentionConfig {
+        keep_hourly: 24,
+        ..Default::default()
+    })
+    .unwrap();
+    let expired = policy.expired(&hourly, now);
+    assert_eq!(expired.len(), 48);
+    assert_eq!(
+        expired.last().unwrap().manifest_name,
+        hourly[47].manifest_name
+    );
+
+    assert_eq!(RetentionPolicy::MaxAge(30).expired(&daily, now).len(), 370);
+    assert!(RetentionPolicy::from_config(&RetentionConfig::default()).is_err());
+}
+
+#[test]
+fn test_quota_prunes_oldest_first_but_keeps_newest() {
+    let first = Utc.with_ymd_and_hms(2024, 6, 1, 2, 0, 0).unwrap();
+    let mut entries = catalog_entries(first, chrono::Duration::days(1), 5);
+    // The newest dump suddenly grew tenfold.
+    entries[4].manifest.size = 10;
+
+    let quota = Quota::from_config(Some(&QuotaConfig {
+        max_total_bytes: 12,
+        min_keep: 1,
+    }))
+    .unwrap()
+    .unwrap();
+    let removed: Vec<_> = quota
+        .over_quota(&entries)
+        .iter()
+        .map(|entry| entry.manifest_name.clone())
+        .collect();
+    assert_eq!(
+        removed,
+        [
+            entries[0].manifest_name.clone(),
+            entries[1].manifest_name.clone()
+        ]
+    );
+
+    // Over quota on its own, the newest backup of each service still survives.
+    let mut replica = catalog_entries(first, chrono::Duration::days(1), 2);
+    for entry in &mut replica {
+        entry.manifest.alias = "db_replica".to_string();
+    }
+    let mut all = [entries.clone(), replica].concat();
+    all.sort_by_key(|entry| entry.manifest.finished_at);
+    let quota = Quota {
+        max_total_bytes: 1,
+        min_keep: 1,
+    };
+    assert_eq!(quota.over_quota(&all).len(), 5);
+
+    assert!(
+        Quota::from_config(Some(&QuotaConfig {
+            max_total_bytes: 1,
+            min_keep: 0
+        }))
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_cleanup_plan_applies_retention_then_quotas() {
+    let dir = env::temp_dir().join(format!("bus-cleanup-plan-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for day in 1..=4 {
+        for alias in ["db", "analytics"] {
+            write_stored_backup(
+                &dir,
+                alias,
+                &format!("postgres_{}_2024-06-0{}_02:00:00.sql", alias, day),
+                &format!("2024-06-0{}T02:00:05Z", day),
+            );
+        }
+    }
+
+    let destination = Destination {
+        backend: Box::new(LocalStorage::new(&LocalConfig {
+            path: dir.to_str().unwrap().to_string(),
+        })),
+        retention: RetentionPolicy::Gfs(RetentionConfig {
+            keep_last: 3,
+            ..Default::default()
+        }),
+    };
+    let quota = Quota {
+        max_total_bytes: 8,
+        min_keep: 1,
+    };
+
+    let planned = plan_cleanup(&destination, &ServiceType::Postgres, "db", Some(&quota))
+        .await
+        .unwrap();
+    let names: Vec<_> = planned
+        .iter()
+        .map(|r| r.entry.manifest.artifact.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "postgres_db_2024-06-01_02:00:00.sql",
+            "postgres_db_2024-06-02_02:00:00.sql"
+        ]
+    );
+
+    // 2 of db and 4 of analytics remain: the total quota takes the oldest analytics backups.
+    let total = Quota {
+        max_total_bytes: 16,
+        min_keep: 1,
+    };
+    let over = plan_total_quota(&destination, &total, &planned)
+        .await
+        .unwrap();
+    let names: Vec<_> = over
+        .iter()
+        .map(|r| r.entry.manifest.artifact.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "postgres_analytics_2024-06-01_02:00:00.sql",
+            "postgres_analytics_2024-06-02_02:00:00.sql"
+        ]
+    );
+
+    // Planning deletes nothing.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 16);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_prune_previews_then_removes_from_destinations_and_staging() {
+    let dir = env::temp_dir().join(format!("bus-prune-{}", std::process::id()));
+    let stored = dir.join("stored");
+    let staging = dir.join(".staging");
+    std::fs::create_dir_all(&stored).unwrap();
+    std::fs::create_dir_all(&staging).unwrap();
+
+    let config: Config = toml::from_str(&format!(
+        r#"[common]
+backup_dir = "{dir}"
+retention_days = 7
+
+[[services]]
+type = "postgres"
+alias = "db"
+schedule = {{ interval_seconds = 3600 }}
+connection = {{ service_type = "postgres", host = "localhost", username = "postgres", database = "app" }}
+
+[[services.destinations]]
+type = "local"
+path = "{dir}/stored"
+"#,
+        dir = dir.display()
+    ))
+    .unwrap();
+
+    let recent = Utc::now().to_rfc3339();
+    write_stored_backup(&stored, "db", "postgres_db_old.sql", "2024-06-07T02:00:05Z");
+    write_stored_backup(&stored, "db", "postgres_db_new.sql", &recent);
+    // Left in staging by a destination that was down; pruned like the stored copies.
+    write_stored_backup(
+        &staging,
+        "db",
+        "postgres_db_stuck.sql",
+        "2024-06-07T02:00:05Z",
+    );
+
+    commands::prune(&config, None, true).await.unwrap();
+    assert!(stored.join("postgres_db_old.sql").exists());
+    assert!(staging.join("postgres_db_stuck.sql").exists());
+
+    commands::prune(&config, Some("db"), false).await.unwrap();
+    assert!(!stored.join("postgres_db_old.sql").exists());
+    assert!(!stored.join("postgres_db_old.sql.manifest.json").exists());
+    assert!(stored.join("postgres_db_new.sql").exists());
+    assert_eq!(std::fs::read_dir(&staging).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cron_schedule_in_timezone() {
+    let config = ScheduleConfig {
+        interval_seconds: None,
+        cron: Some("30 23 * * 1-5".to_string()),
+        timezone: Some("Europe/Berlin".to_string()),
+        start_time: None,
+        retry: None,
+    };
+
+    // Friday 2024-06-07 22:00 UTC is already past 23:30 in Berlin (UTC+2).
+    let now = Utc.with_ymd_and_hms(2024, 6, 7, 22, 0, 0).unwrap();
+    let schedule = Schedule::from_config(&config, now).unwrap();
+
+    let next = schedule.next_run(now).unwrap();
+    assert_eq!(next, Utc.with_ymd_and_hms(2024, 6, 10, 21, 30, 0).unwrap());
+
+    let after = schedule.next_run(next).unwrap();
+    assert_eq!(after, Utc.with_ymd_and_hms(2024, 6, 11, 21, 30, 0).unwrap());
+}
+
+#[test]
+fn test_schedule_requires_exactly_one_trigger() {
+    let now = Utc::now();
+    let mut config = ScheduleConfig {
+        interval_seconds: Some(3600),
+        cron: Some("0 2 * * *".to_string()),
+        timezone: None,
+        start_time: None,
+        retry: None,
+    };
+    assert!(Schedule::from_config(&config, now).is_err());
+
+    config.cron = None;
+    config.interval_seconds = Some(0);
+    assert!(Schedule::from_config(&config, now).is_err());
+
+    config.interval_seconds = None;
+    assert!(Schedule::from_config(&config, now).is_err());
+}
+
+#[test]
+fn test_interval_without_start_time_runs_immediately() {
+    let config = ScheduleConfig {
+        interval_seconds: Some(3600),
+        cron: None,
+        timezone: None,
+        start_time: None,
+        retry: None,
+    };
+
+    let created = Utc.with_ymd_and_hms(2024, 6, 7, 10, 0, 0).unwrap();
+    let schedule = Schedule::from_config(&config, created).unwrap();
+
+    // The scheduler starts a moment after the schedule was built.
+    let started = created + chrono::Duration::milliseconds(5);
+    let first = schedule.first_run(started).unwrap();
+    assert_eq!(first, created);
+    assert_eq!(
+        schedule.next_run(started.max(first)).unwrap(),
+        Utc.with_ymd_and_hms(2024, 6, 7, 11, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_interval_schedule_aligned_to_start_time() {
+    let config = ScheduleConfig {
+        interval_seconds: Some(6 * 3600),
+        cron: None,
+        timezone: Some("UTC".to_string()),
+        start_time: Some("02:00".to_string()),
+        retry: None,
+    };
+
+    // Restarting at any point keeps the 02:00/08:00/14:00/20:00 phase.
+    let now = Utc.with_ymd_and_hms(2024, 6, 7, 10, 17, 0).unwrap();
+    let schedule = Schedule::from_config(&config, now).unwrap();
+    assert_eq!(
+        schedule.first_run(now).unwrap(),
+        Utc.with_ymd_and_hms(2024, 6, 7, 14, 0, 0).unwrap()
+    );
+
+    let late = Utc.with_ymd_and_hms(2024, 6, 7, 21, 0, 0).unwrap();
+    assert_eq!(
+        schedule.next_run(late).unwrap(),
+        Utc.with_ymd_and_hms(2024, 6, 8, 2, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_interval_not_dividing_a_day_keeps_its_spacing_across_midnight() {
+    let config = ScheduleConfig {
+        interval_seconds: Some(7 * 3600),
+        cron: None,
+        timezone: Some("UTC".to_string()),
+        start_time: Some("02:00".to_string()),
+        retry: None,
+    };
+
+    let now = Utc.with_ymd_and_hms(2024, 6, 7, 17, 0, 0).unwrap();
+    let schedule = Schedule::from_config(&config, now).unwrap();
+
+    let evening = schedule.first_run(now).unwrap();
+    assert_eq!(evening, Utc.with_ymd_and_hms(2024, 6, 7, 20, 0, 0).unwrap());
+
+    // The next run is a full interval later, not back at 02:00 on the new day.
+    let night = schedule.next_run(evening).unwrap();
+    assert_eq!(night, Utc.with_ymd_and_hms(2024, 6, 8, 3, 0, 0).unwrap());
+    assert_eq!(
+        schedule.next_run(night).unwrap(),
+        Utc.with_ymd_and_hms(2024, 6, 8, 10, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_daily_schedule_follows_dst() {
+    let config = ScheduleConfig {
+        interval_seconds: Some(86400),
+        cron: None,
+        timezone: Some("Europe/Berlin".to_string()),
+        start_time: Some("02:30".to_string()),
+        retry: None,
+    };
+
+    // 2024-03-31 02:30 does not exist in Berlin; the run moves to 03:00 CEST.
+    let before_switch = Utc.with_ymd_and_hms(2024, 3, 30, 1, 30, 0).unwrap();
+    let schedule = Schedule::from_config(&config, before_switch).unwrap();
+
+    let skipped = schedule.next_run(before_switch).unwrap();
+    assert_eq!(skipped, Utc.with_ymd_and_hms(2024, 3, 31, 1, 0, 0).unwrap());
 
-    assert_eq!(config.services[0].connection.get_password(), "secret123");
+    let summer = schedule.next_run(skipped).unwrap();
+    assert_eq!(summer, Utc.with_ymd_and_hms(2024, 4, 1, 0, 30, 0).unwrap());
+}
+
+#[test]
+fn test_dump_tool_error_reports_exit_code_and_stderr() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let output = std::process::Output {
+        status: std::process::ExitStatus::from_raw(1 << 8),
+        stdout: Vec::new(),
+        stderr: b"FATAL:  password authentication failed for user \"app\"\n".to_vec(),
+    };
+
+    let error = BusError::tool_failed("pg_dump", &output);
+    assert_eq!(error.kind(), "dump_tool");
+    assert!(matches!(
+        error,
+        BusError::DumpTool {
+            exit_code: Some(1),
+            ..
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "pg_dump failed with exit code 1: FATAL:  password authentication failed for user \"app\""
+    );
+}
+
+#[test]
+fn test_retry_policy_backoff() {
+    let config = RetryConfig {
+        max_attempts: 5,
+        initial_delay_seconds: 10,
+        multiplier: 3.0,
+        max_delay_seconds: 60,
+        jitter: 0.0,
+    };
+    let policy = RetryPolicy::from_config(Some(&config)).unwrap();
+
+    let delays: Vec<u64> = (1..=4).map(|n| policy.delay(n).as_secs()).collect();
+    assert_eq!(delays, vec![10, 30, 60, 60]);
+
+    assert_eq!(RetryPolicy::from_config(None).unwrap().max_attempts, 1);
+
+    let jittered = RetryPolicy::from_config(Some(&RetryConfig {
+        jitter: 0.5,
+        ..config
+    }))
+    .unwrap();
+    let delay = jittered.delay(2).as_secs_f64();
+    assert!((15.0..=45.0).contains(&delay));
+}
+
+#[test]
+fn test_compression_resolution() {
+    let common = CompressionConfig {
+        codec: Codec::Zstd,
+        level: Some(19),
+    };
+    let resolved = Compression::resolve(Some(&common), None).unwrap();
+    assert_eq!((resolved.codec, resolved.level), (Codec::Zstd, 19));
+
+    let options = HashMap::from([("compression".to_string(), "xz".to_string())]);
+    let resolved = Compression::resolve(Some(&common), Some(&options)).unwrap();
+    assert_eq!((resolved.codec, resolved.level), (Codec::Xz, 6));
+
+    let options = HashMap::from([
+        ("compression".to_string(), "gzip".to_string()),
+        ("compression_level".to_string(), "12".to_string()),
+    ]);
+    assert!(Compression::resolve(None, Some(&options)).is_err());
+}
+
+#[tokio::test]
+async fn test_pipeline_streams_tool_output_into_artifact() {
+    let dir = env::temp_dir().join(format!("bus-pipeline-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pipeline =
+        ArtifactPipeline::new(&dir, Compression::new(Codec::Zstd, Some(10)).unwrap(), None);
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args([
+        "-c",
+        "for i in $(seq 1 1000); do echo \"INSERT INTO t VALUES ($i);\"; done",
+    ]);
+    let artifact = pipeline.run(cmd, "sh", "dump.sql").await.unwrap();
+
+    assert!(artifact.path.ends_with("dump.sql.zst"));
+    assert!(!dir.join("dump.sql").exists());
+
+    let stored = std::fs::read(&artifact.path).unwrap();
+    assert_eq!(artifact.size, stored.len() as u64);
+    assert_eq!(
+        artifact.sha256,
+        format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(&stored))
+    );
+
+    let decoded = String::from_utf8(zstd::decode_all(stored.as_slice()).unwrap()).unwrap();
+    assert_eq!(artifact.uncompressed_size, decoded.len() as u64);
+    assert_eq!(decoded.lines().count(), 1000);
+    assert_eq!(decoded.lines().last(), Some("INSERT INTO t VALUES (1000);"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_restore_feeds_decoded_artifact_to_tool() {
+    let dir = env::temp_dir().join(format!("bus-feed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let keys = EncryptionConfig::Passphrase {
+        passphrase: Secret::from("correct horse"),
+    };
+    let pipeline = ArtifactPipeline::new(
+        &dir,
+        Compression::new(Codec::Gzip, None).unwrap(),
+        Encryption::resolve(Some(&keys), None).unwrap(),
+    );
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args(["-c", "seq 1 50000"]);
+    let artifact = pipeline.run(cmd, "sh", "dump.sql").await.unwrap();
+    let artifact = Path::new(&artifact.path);
+    assert_eq!(sha256_file(artifact).await.unwrap().len(), 64);
+
+    let restored = dir.join("restored.sql");
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args(["-c", &format!("cat > {}", restored.display())]);
+    let fed = feed(cmd, "sh", artifact, Some(&keys)).await.unwrap();
+
+    let expected: String = (1..=50000).map(|i| format!("{}\n", i)).collect();
+    assert_eq!(fed, expected.len() as u64);
+    assert_eq!(std::fs::read_to_string(&restored).unwrap(), expected);
+
+    // A tool that gives up early is reported with its own error, not the broken pipe.
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args(["-c", "echo 'relation already exists' >&2; exit 3"]);
+    match feed(cmd, "psql", artifact, Some(&keys)).await {
+        Err(BusError::DumpTool {
+            tool,
+            exit_code,
+            stderr,
+        }) => {
+            assert_eq!(tool, "psql");
+            assert_eq!(exit_code, Some(3));
+            assert_eq!(stderr, "relation already exists");
+        }
+        other => panic!("expected a tool error, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_pipeline_discards_artifact_when_tool_fails() {
+    let dir = env::temp_dir().join(format!("bus-pipeline-fail-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pipeline = ArtifactPipeline::new(&dir, Compression::new(Codec::Gzip, None).unwrap(), None);
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args(["-c", "echo partial; echo 'connection refused' >&2; exit 3"]);
+    let error = pipeline.run(cmd, "pg_dump", "dump.sql").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        BusError::DumpTool { exit_code: Some(3), ref stderr, .. } if stderr == "connection refused"
+    ));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn read_artifact(path: &str, keys: &EncryptionConfig) -> Result<String, BusError> {
+    let mut reader = open_artifact(std::path::Path::new(path), Some(keys))?;
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut reader, &mut decoded)
+        .map_err(|e| BusError::Encryption(e.to_string()))?;
+    Ok(decoded)
+}
+
+#[tokio::test]
+async fn test_passphrase_encrypted_artifact_round_trip() {
+    let dir = env::temp_dir().join(format!("bus-passphrase-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let keys = EncryptionConfig::Passphrase {
+        passphrase: Secret::from("correct horse"),
+    };
+    let encryption = Encryption::resolve(Some(&keys), None).unwrap();
+    let pipeline = ArtifactPipeline::new(
+        &dir,
+        Compression::new(Codec::None, None).unwrap(),
+        encryption,
+    );
+
+    // Uncompressed and larger than one chunk, so the stream spans several sealed chunks.
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args([
+        "-c",
+        "for i in $(seq 1 20000); do echo \"INSERT INTO t VALUES ($i);\"; done",
+    ]);
+    let artifact = pipeline.run(cmd, "sh", "dump.sql").await.unwrap();
+    assert!(artifact.path.ends_with("dump.sql.enc"));
+
+    let decoded = read_artifact(&artifact.path, &keys).unwrap();
+    assert_eq!(decoded.lines().count(), 20000);
+    assert_eq!(
+        decoded.lines().last(),
+        Some("INSERT INTO t VALUES (20000);")
+    );
+
+    let wrong = EncryptionConfig::Passphrase {
+        passphrase: Secret::from("wrong horse"),
+    };
+    assert!(read_artifact(&artifact.path, &wrong).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_age_encrypted_artifact_round_trip() {
+    use age::secrecy::ExposeSecret;
+
+    let dir = env::temp_dir().join(format!("bus-age-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let identity = age::x25519::Identity::generate();
+    let identity_file = dir.join("backup.key");
+    std::fs::write(
+        &identity_file,
+        format!("{}\n", identity.to_string().expose_secret()),
+    )
+    .unwrap();
+
+    let keys = EncryptionConfig::Age {
+        recipients: vec![identity.to_public().to_string()],
+        identity_file: Some(identity_file.to_str().unwrap().to_string()),
+    };
+    let encryption = Encryption::resolve(None, Some(&keys)).unwrap();
+    let pipeline = ArtifactPipeline::new(
+        &dir,
+        Compression::new(Codec::Gzip, None).unwrap(),
+        encryption,
+    );
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.args(["-c", "echo 'CREATE TABLE t (id int);'"]);
+    let artifact = pipeline.run(cmd, "sh", "dump.sql").await.unwrap();
+    assert!(artifact.path.ends_with("dump.sql.gz.age"));
+
+    let decoded = read_artifact(&artifact.path, &keys).unwrap();
+    assert_eq!(decoded, "CREATE TABLE t (id int);\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_manifest_never_contains_secrets() {
+    let keys = EncryptionConfig::Passphrase {
+        passphrase: Secret::from("correct horse"),
+    };
+    let started_at = Utc.with_ymd_and_hms(2025, 3, 1, 2, 0, 0).unwrap();
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        alias: "main-db".to_string(),
+        service_type: ServiceType::Postgres,
+        artifact: "postgres_main-db_2025-03-01_02:00:00.000000.sql.gz.enc".to_string(),
+        tool: "pg_dump".to_string(),
+        tool_version: Some("pg_dump (PostgreSQL) 16.4".to_string()),
+        server_version: Some("16.4".to_string()),
+        started_at,
+        finished_at: started_at + chrono::Duration::milliseconds(1500),
+        duration_seconds: 1.5,
+        uncompressed_size: 4096,
+        size: 512,
+        sha256: "ab".repeat(32),
+        compression: Compression::new(Codec::Gzip, None).unwrap(),
+        encryption: Encryption::resolve(Some(&keys), None).unwrap(),
+        backup_options: [("format".to_string(), "plain".to_string())].into(),
+        verification: None,
+    };
+
+    let json: serde_json::Value = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(json["service_type"], "postgres");
+    assert_eq!(json["compression"]["codec"], "gzip");
+    assert_eq!(json["encryption"]["method"], "passphrase");
+    assert_eq!(json["backup_options"]["format"], "plain");
+    assert_eq!(json["started_at"], "2025-03-01T02:00:00Z");
+    assert!(!json.to_string().contains("correct horse"));
+}
+
+#[test]
+fn test_cli_subcommands() {
+    use clap::{CommandFactory, Parser};
+
+    use crate::{Cli, Command};
+
+    Cli::command().debug_assert();
+
+    // Without a subcommand Bus runs as a daemon, as it always has.
+    let cli = Cli::try_parse_from(["bus", "--prefix", "bus", "--config", "bus.toml"]).unwrap();
+    assert!(cli.command.is_none());
+
+    let cli = Cli::try_parse_from(["bus", "-c", "bus.toml", "run", "main-db"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Run { alias }) if alias == "main-db"));
+
+    let cli = Cli::try_parse_from(["bus", "-c", "bus.toml", "list", "--service", "cache"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::List { service: Some(s) }) if s == "cache"));
+
+    assert!(Cli::try_parse_from(["bus", "-c", "bus.toml", "run"]).is_err());
+
+    // Restores name the backup or explicitly ask for the latest one, never both.
+    let cli = Cli::try_parse_from(["bus", "-c", "bus.toml", "restore", "-s", "db", "--latest"]);
+    assert!(matches!(
+        cli.unwrap().command,
+        Some(Command::Restore {
+            latest: true,
+            backup: None,
+            ..
+        })
+    ));
+    assert!(Cli::try_parse_from(["bus", "-c", "bus.toml", "restore", "-s", "db"]).is_err());
+    assert!(
+        Cli::try_parse_from([
+            "bus", "-c", "bus.toml", "restore", "-s", "db", "--latest", "--backup", "x"
+        ])
+        .is_err()
+    );
+
+    let cli = Cli::try_parse_from([
+        "bus", "-c", "bus.toml", "verify", "-s", "db", "--backup", "x",
+    ]);
+    assert!(matches!(
+        cli.unwrap().command,
+        Some(Command::Verify { backup: Some(ref backup), .. }) if backup == "x"
+    ));
+    assert!(Cli::try_parse_from(["bus", "-c", "bus.toml", "verify", "-s", "db"]).is_err());
+}
+
+#[test]
+fn test_validation_reports_every_problem_with_its_line() {
+    let toml_content = r#"[common]
+backup_dir = "/tmp/backups"
+
+[[services]]
+type = "postgres"
+alias = "main-db"
+backup_options = { format = "custom", fromat = "plain" }
+
+[services.connection]
+service_type = "redis"
+host = "localhost"
+password = "secret"
+
+[services.schedule]
+interval_seconds = 0
+
+[[services]]
+type = "redis"
+alias = "main-db"
+
+[services.connection]
+service_type = "redis"
+host = "localhost"
+password = "secret"
+
+[services.schedule]
+interval_seconds = 3600
+timezone = "Mars/Olympus"
+start_time = "25:00"
+"#;
+
+    let config: Config = toml::from_str(toml_content).unwrap();
+    let problems = validation::check(&config);
+
+    let paths: Vec<&str> = problems.iter().map(|p| p.path.as_str()).collect();
     assert_eq!(
-        config.services[0].connection.database,
-        Some("testdb".to_string())
+        paths,
+        [
+            "services[0].connection.service_type",
+            "services[0].schedule.interval_seconds",
+            "services[0].backup_options.fromat",
+            "services[1].alias",
+            "services[1].schedule.timezone",
+            "services[1].schedule.start_time",
+        ]
     );
+
+    let report = validation::report(Path::new("bus.toml"), toml_content, &problems);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "6 problem(s) in bus.toml:");
+    assert!(lines[1].starts_with("  bus.toml:10: services[0].connection.service_type: 'redis'"));
+    assert!(lines[2].starts_with("  bus.toml:15: services[0].schedule.interval_seconds:"));
+    assert!(lines[3].starts_with("  bus.toml:7: services[0].backup_options.fromat: unknown"));
+    assert!(lines[4].contains("bus.toml:19: services[1].alias: duplicate alias 'main-db'"));
+    assert!(lines[5].starts_with("  bus.toml:28: services[1].schedule.timezone:"));
+    assert!(lines[6].starts_with("  bus.toml:29: services[1].schedule.start_time:"));
+}
+
+#[test]
+fn test_validation_reports_every_problem_in_one_schedule() {
+    let config: Config = toml::from_str(
+        r#"[common]
+backup_dir = "/tmp/backups"
+
+[[services]]
+type = "redis"
+alias = "cache"
+connection = { service_type = "redis", host = "localhost", password = "" }
+schedule = { interval_seconds = 0, timezone = "Mars/Olympus", start_time = "25:00" }
+
+[[services]]
+type = "redis"
+alias = "sessions"
+connection = { service_type = "redis", host = "localhost", password = "" }
+schedule = { interval_seconds = 3600, cron = "0 2 * * *", timezone = "Mars/Olympus" }
+"#,
+    )
+    .unwrap();
+
+    let problems: Vec<String> = validation::check(&config)
+        .into_iter()
+        .map(|problem| format!("{}: {}", problem.path, problem.message))
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            "services[0].schedule.timezone: Unknown timezone: Mars/Olympus",
+            "services[0].schedule.start_time: Invalid start_time '25:00', expected HH:MM",
+            "services[0].schedule.interval_seconds: 'interval_seconds' must be greater than zero",
+            "services[1].schedule.timezone: Unknown timezone: Mars/Olympus",
+            "services[1].schedule: Only one of 'cron' or 'interval_seconds' can be set in a schedule",
+        ]
+    );
+}
+
+#[test]
+fn test_verify_never_uses_the_database_being_backed_up() {
+    let toml_content = r#"[common]
+backup_dir = "/tmp/backups"
+
+[[services]]
+type = "postgres"
+alias = "main-db"
+schedule = { interval_seconds = 3600 }
+verify = { tables = ["users"], sql = "SELECT count(*) > 0 FROM users" }
+
+[services.connection]
+service_type = "postgres"
+host = "localhost"
+username = "postgres"
+database = "app"
+
+[[services]]
+type = "postgres"
+alias = "replica"
+schedule = { interval_seconds = 3600 }
+verify = { database = "app" }
+
+[services.connection]
+service_type = "postgres"
+host = "localhost"
+username = "postgres"
+database = "app"
+
+[[services]]
+type = "redis"
+alias = "cache"
+schedule = { interval_seconds = 3600 }
+verify = {}
+
+[services.connection]
+service_type = "redis"
+host = "localhost"
+password = ""
+
+[[services]]
+type = "postgres"
+alias = "orders"
+schedule = { interval_seconds = 3600 }
+verify = {}
+encryption = { method = "age", recipients = ["RECIPIENT"] }
+
+[services.connection]
+service_type = "postgres"
+host = "localhost"
+username = "postgres"
+database = "orders"
+"#;
+
+    // Without the private key, the backup can be encrypted but not restored.
+    let recipient = age::x25519::Identity::generate().to_public().to_string();
+    let config: Config = toml::from_str(&toml_content.replace("RECIPIENT", &recipient)).unwrap();
+    let connection = config.services[0].connection.as_postgres().unwrap();
+    let verify = config.services[0].verify.as_ref().unwrap();
+    assert_eq!(verify.min_tables, 1);
+
+    let (scratch, database) = connection.scratch(verify, "main-db").unwrap();
+    assert_eq!(database, "bus_verify_main-db");
+    assert_eq!((scratch.host.as_str(), scratch.port), ("localhost", 5432));
+
+    // Another server may use the same name; only the database being backed up is refused.
+    let mut elsewhere = config.services[1].verify.clone().unwrap();
+    elsewhere.host = Some("scratch.internal".to_string());
+    let (scratch, database) = connection.scratch(&elsewhere, "replica").unwrap();
+    assert_eq!(
+        (scratch.host.as_str(), database.as_str()),
+        ("scratch.internal", "app")
+    );
+
+    // The same server under another name is still the same server.
+    let mut loopback = config.services[1].verify.clone().unwrap();
+    loopback.host = Some("127.0.0.1".to_string());
+    assert!(connection.scratch(&loopback, "replica").is_err());
+
+    let paths: Vec<String> = validation::check(&config)
+        .into_iter()
+        .map(|problem| format!("{}: {}", problem.path, problem.message))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "services[1].verify.database: 'app' is the database being backed up; verification drops its scratch database",
+            "services[2].verify: redis services can't be verified",
+            "services[3].verify: verification restores each backup, so age 'encryption' needs an 'identity_file'",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_verify_restores_into_scratch_database_and_checks_it() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("bus-verify-{}", std::process::id()));
+    let bin = dir.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+
+    // Stands in for psql: records every call and answers the queries verification runs.
+    let psql = bin.join("psql");
+    std::fs::write(
+        &psql,
+        format!(
+            r#"#!/bin/sh
+printf '%s\n' "$*" >> {log}
+case "$*" in
+  *information_schema.tables*) echo 3 ;;
+  *'FROM "sales"."Orders"'*) echo 42 ;;
+  *'SELECT bool_and(paid)'*) echo t ;;
+  *' -c '*) ;;
+  *) cat > {dir}/restored.sql ;;
+esac
+"#,
+            log = dir.join("psql.log").display(),
+            dir = dir.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&psql, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let config: Config = toml::from_str(&format!(
+        r#"[common]
+backup_dir = "{dir}"
+
+[[services]]
+type = "postgres"
+alias = "shop"
+schedule = {{ interval_seconds = 3600 }}
+verify = {{ tables = ["sales.Orders"], sql = "SELECT bool_and(paid) FROM sales.\"Orders\"" }}
+
+[services.connection]
+service_type = "postgres"
+host = "localhost"
+username = "postgres"
+database = "shop"
+bin_dir = "{bin}"
+"#,
+        dir = dir.display(),
+        bin = bin.display()
+    ))
+    .unwrap();
+    let job = ServiceFactory::create_service(config.services[0].clone(), &config.common).unwrap();
+
+    let artifact = dir.join("postgres_shop.sql");
+    std::fs::write(&artifact, "CREATE TABLE sales.\"Orders\" ();\n").unwrap();
+    let verification = job
+        .verify(&artifact, &RestoreOptions::default())
+        .await
+        .unwrap();
+
+    assert!(verification.passed, "{:?}", verification.error);
+    assert_eq!(verification.database, "bus_verify_shop");
+    assert_eq!(verification.tables, Some(3));
+    assert_eq!(verification.row_counts.get("sales.Orders"), Some(&42));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("restored.sql")).unwrap(),
+        "CREATE TABLE sales.\"Orders\" ();\n"
+    );
+
+    // The scratch database is recreated before and dropped after the check.
+    let log = std::fs::read_to_string(dir.join("psql.log")).unwrap();
+    let statements: Vec<&str> = log
+        .lines()
+        .filter_map(|line| line.split(" -c ").nth(1))
+        .filter(|sql| sql.contains("DATABASE"))
+        .collect();
+    assert_eq!(
+        statements,
+        [
+            "DROP DATABASE IF EXISTS \"bus_verify_shop\"",
+            "CREATE DATABASE \"bus_verify_shop\"",
+            "DROP DATABASE IF EXISTS \"bus_verify_shop\"",
+        ]
+    );
+    assert!(!log.contains("-d shop "));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A service type that only knows how to take backups.
+struct BackupOnly {
+    schedule: ScheduleConfig,
+}
+
+#[async_trait::async_trait]
+impl BackupService for BackupOnly {
+    async fn backup(&self, _timestamp: &str) -> Result<crate::pipeline::Artifact, BusError> {
+        Err(BusError::Config("not needed".into()))
+    }
+
+    fn get_schedule(&self) -> &ScheduleConfig {
+        &self.schedule
+    }
+
+    fn alias(&self) -> &str {
+        "files"
+    }
+
+    fn service_type(&self) -> &ServiceType {
+        &ServiceType::Redis
+    }
+
+    fn backup_options(&self) -> Option<&HashMap<String, String>> {
+        None
+    }
+}
+
+#[tokio::test]
+async fn test_service_hooks_default_to_unsupported() {
+    let service = BackupOnly {
+        schedule: ScheduleConfig {
+            interval_seconds: Some(3600),
+            cron: None,
+            timezone: None,
+            start_time: None,
+            retry: None,
+        },
+    };
+
+    assert_eq!(service.describe(), Capabilities::default());
+    assert_eq!(service.describe().to_string(), "backup only");
+    assert!(matches!(
+        service.restore(Path::new("dump"), &RestoreOptions::default()).await,
+        Err(BusError::Unsupported(ref what)) if what == "restoring redis services"
+    ));
+    assert!(matches!(
+        service.health_check().await,
+        Err(BusError::Unsupported(_))
+    ));
+    assert!(
+        service
+            .verify(Path::new("dump"), &RestoreOptions::default())
+            .await
+            .is_none()
+    );
+
+    let config: Config = toml::from_str(
+        r#"[common]
+backup_dir = "/tmp/backups"
+
+[[services]]
+type = "postgres"
+alias = "main-db"
+schedule = { interval_seconds = 3600 }
+connection = { service_type = "postgres", host = "localhost", username = "postgres", database = "app" }
+"#,
+    )
+    .unwrap();
+    let mut service = config.services[0].clone();
+    let postgres = ServiceFactory::create_service(service.clone(), &config.common).unwrap();
+    assert_eq!(
+        postgres.describe().to_string(),
+        "backup, restore, health check"
+    );
+
+    service.verify = Some(toml::from_str("tables = [\"users\"]").unwrap());
+    let postgres = ServiceFactory::create_service(service, &config.common).unwrap();
+    assert!(postgres.describe().verify);
+}
+
+/// A destination whose uploads fail while it is offline, like a remote during an outage.
+struct FlakyStorage {
+    inner: LocalStorage,
+    online: Arc<AtomicBool>,
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for FlakyStorage {
+    async fn put(&self, source: &Path, name: &str) -> Result<(), BusError> {
+        if !self.online.load(Ordering::SeqCst) {
+            return Err(BusError::remote(self, "connection refused"));
+        }
+        self.inner.put(source, name).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<crate::storage::StoredObject>, BusError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), BusError> {
+        self.inner.delete(name).await
+    }
+
+    async fn get(&self, name: &str, target: &Path) -> Result<(), BusError> {
+        self.inner.get(name, target).await
+    }
+
+    async fn read(&self, name: &str) -> Result<Vec<u8>, BusError> {
+        self.inner.read(name).await
+    }
+
+    async fn stat(&self, name: &str) -> Result<crate::storage::StoredObject, BusError> {
+        self.inner.stat(name).await
+    }
+}
+
+impl std::fmt::Display for FlakyStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "flaky {}", self.inner)
+    }
+}
+
+#[tokio::test]
+async fn test_staged_backups_are_redelivered_and_pruned() {
+    let dir = env::temp_dir().join(format!("bus-redeliver-{}", std::process::id()));
+    let staging_dir = dir.join(".staging");
+    std::fs::create_dir_all(&staging_dir).unwrap();
+    let local = |name: &str| {
+        LocalStorage::new(&LocalConfig {
+            path: dir.join(name).display().to_string(),
+        })
+    };
+
+    let online = Arc::new(AtomicBool::new(false));
+    let healthy = Destination {
+        backend: Box::new(local("healthy")),
+        retention: RetentionPolicy::MaxAge(30),
+    };
+    let flaky = Destination {
+        backend: Box::new(FlakyStorage {
+            inner: local("flaky"),
+            online: online.clone(),
+        }),
+        retention: RetentionPolicy::MaxAge(30),
+    };
+    let staging = Destination {
+        backend: Box::new(local(".staging")),
+        retention: RetentionPolicy::MaxAge(30),
+    };
+    let destinations = [&healthy, &flaky];
+
+    let service = BackupOnly {
+        schedule: ScheduleConfig {
+            interval_seconds: Some(3600),
+            cron: None,
+            timezone: None,
+            start_time: None,
+            retry: None,
+        },
+    };
+    let stage = |artifact: &str, finished_at: chrono::DateTime<Utc>| {
+        let path = staging_dir.join(artifact);
+        std::fs::write(&path, "dump").unwrap();
+        std::fs::write(
+            staging_dir.join(format!("{}.manifest.json", artifact)),
+            format!(
+                r#"{{"version": 1, "alias": "files", "service_type": "redis", "artifact": "{}", "finished_at": "{}", "size": 4, "sha256": ""}}"#,
+                artifact,
+                finished_at.to_rfc3339()
+            ),
+        )
+        .unwrap();
+        vec![
+            path.display().to_string(),
+            format!("{}.manifest.json", path.display()),
+        ]
+    };
+
+    // While one destination is down, the backup stays in staging.
+    let recent = "redis_files_recent.rdb";
+    let staged = stage(recent, Utc::now() - chrono::Duration::hours(1));
+    assert!(
+        !BackupScheduler::deliver(&service, &destinations, &staged, &RetryPolicy::none()).await
+    );
+    assert!(staged.iter().all(|file| Path::new(file).exists()));
+    assert!(healthy.backend.stat(recent).await.is_ok());
+    assert!(flaky.backend.stat(recent).await.is_err());
+
+    // Staged backups are pruned under the service's retention like stored ones.
+    let stale = "redis_files_stale.rdb";
+    stage(stale, Utc::now() - chrono::Duration::days(60));
+    BackupScheduler::cleanup_old_backups(&service, &staging, None, None)
+        .await
+        .unwrap();
+    assert!(!staging_dir.join(stale).exists());
+    assert!(staging_dir.join(recent).exists());
+
+    // `common.quota` counts staging too, and keeps the newest `min_keep` there as well.
+    let older = "redis_files_older.rdb";
+    stage(older, Utc::now() - chrono::Duration::hours(2));
+    let total = Quota {
+        max_total_bytes: 4,
+        min_keep: 1,
+    };
+    BackupScheduler::cleanup_old_backups(&service, &staging, None, Some(&total))
+        .await
+        .unwrap();
+    assert!(!staging_dir.join(older).exists());
+    assert!(staging_dir.join(recent).exists());
+
+    // Once it is back, the next cycle delivers what it is missing and clears staging.
+    online.store(true, Ordering::SeqCst);
+    BackupScheduler::redeliver(
+        &service,
+        &destinations,
+        &staging,
+        &staging_dir,
+        &RetryPolicy::none(),
+    )
+    .await;
+    assert!(flaky.backend.stat(recent).await.is_ok());
+    assert!(
+        flaky
+            .backend
+            .stat("redis_files_recent.rdb.manifest.json")
+            .await
+            .is_ok()
+    );
+    assert_eq!(std::fs::read_dir(&staging_dir).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_fetching_a_backup_leaves_its_staged_copy_alone() {
+    let dir = env::temp_dir().join(format!("bus-fetch-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("stored")).unwrap();
+    std::fs::create_dir_all(dir.join(".staging")).unwrap();
+
+    let config: Config = toml::from_str(&format!(
+        r#"[common]
+backup_dir = "{dir}"
+
+[[services]]
+type = "command"
+alias = "ldap"
+schedule = {{ interval_seconds = 3600 }}
+connection = {{ service_type = "command", command = "true" }}
+
+[[services.destinations]]
+type = "local"
+path = "{dir}/stored"
+"#,
+        dir = dir.display()
+    ))
+    .unwrap();
+
+    // One destination has the backup, the other is still waiting for the staged copy.
+    let name = "command_ldap_2025-03-01_02:00:00.000000.out";
+    std::fs::write(dir.join("stored").join(name), "stored copy").unwrap();
+    std::fs::write(dir.join(".staging").join(name), "staged copy").unwrap();
+
+    let output = dir.join("dump.out");
+    commands::decrypt(&config, Path::new(name), Some(&output), Some("ldap"))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "stored copy");
+    assert_eq!(
+        std::fs::read_to_string(dir.join(".staging").join(name)).unwrap(),
+        "staged copy"
+    );
+    assert_eq!(std::fs::read_dir(dir.join(".staging")).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_command_service_backs_up_what_the_command_writes() {
+    let dir = env::temp_dir().join(format!("bus-command-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("work")).unwrap();
+    let keys = EncryptionConfig::Passphrase {
+        passphrase: Secret::from("correct horse"),
+    };
+
+    let toml_content = format!(
+        r#"[common]
+backup_dir = "{dir}"
+encryption = {{ method = "passphrase", passphrase = "correct horse" }}
+
+[[services]]
+type = "command"
+alias = "ldap"
+schedule = {{ interval_seconds = 3600 }}
+
+[services.connection]
+service_type = "command"
+command = "sh"
+args = ["-c", "echo \"$GREETING from $(basename $(pwd))\""]
+env = {{ GREETING = "cmd:echo hello" }}
+working_dir = "{dir}/work"
+extension = "ldif"
+
+[[services]]
+type = "command"
+alias = "files"
+schedule = {{ interval_seconds = 3600 }}
+
+[services.connection]
+service_type = "command"
+command = "sh"
+args = ["-c", "echo 'to the file'; echo 'written' > {dir}/dump.tar"]
+output_file = "{dir}/dump.tar"
+extension = "tar"
+
+[[services]]
+type = "command"
+alias = "stuck"
+schedule = {{ interval_seconds = 3600 }}
+
+[services.connection]
+service_type = "command"
+command = "sh"
+args = ["-c", "echo partial; sleep 30 & setsid sleep 30 & sleep 30"]
+timeout_seconds = 1
+
+[[services]]
+type = "command"
+alias = "relative"
+schedule = {{ interval_seconds = 3600 }}
+
+[services.connection]
+service_type = "command"
+command = "sh"
+args = ["-c", "echo 'in work' > dump.ldif; [ -z \"$FAIL\" ]"]
+env = {{ FAIL = "" }}
+working_dir = "{dir}/work"
+output_file = "dump.ldif"
+"#,
+        dir = dir.display()
+    );
+    let config: Config = toml::from_str(&toml_content).unwrap();
+    assert!(validation::check(&config).is_empty());
+
+    let job = |index: usize| {
+        ServiceFactory::create_service(config.services[index].clone(), &config.common).unwrap()
+    };
+
+    let artifact = job(0).backup("2025-03-01_02:00:00.000000").await.unwrap();
+    assert!(
+        artifact
+            .path
+            .ends_with("command_ldap_2025-03-01_02:00:00.000000.ldif.gz.enc")
+    );
+    assert_eq!(artifact.tool, "sh");
+    // An arbitrary script is never run with `--version`.
+    assert_eq!(artifact.tool_version, None);
+    assert_eq!(
+        read_artifact(&artifact.path, &keys).unwrap(),
+        "hello from work\n"
+    );
+
+    let artifact = job(1).backup("2025-03-01_02:00:00.000000").await.unwrap();
+    assert_eq!(read_artifact(&artifact.path, &keys).unwrap(), "written\n");
+    assert!(!dir.join("dump.tar").exists());
+
+    let started = std::time::Instant::now();
+    let error = job(2)
+        .backup("2025-03-01_02:00:00.000000")
+        .await
+        .unwrap_err();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(error.to_string(), "sh timed out after 1 seconds");
+    assert_eq!(error.kind(), "timeout");
+    assert!(!error.is_retryable());
+    let staged: Vec<_> = std::fs::read_dir(dir.join(".staging"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("command_stuck_"))
+        .collect();
+    assert!(staged.is_empty(), "left behind: {:?}", staged);
+
+    // A relative output_file is found in working_dir, where the command wrote it.
+    let artifact = job(3).backup("2025-03-01_02:00:00.000000").await.unwrap();
+    assert_eq!(read_artifact(&artifact.path, &keys).unwrap(), "in work\n");
+    assert!(!dir.join("work/dump.ldif").exists());
+
+    // The plain dump of a failed run is not left behind either.
+    let mut failing = config.services[3].clone();
+    if let ConnectionConfig::Command(command) = &mut failing.connection {
+        command.env.insert("FAIL".into(), Secret::from("yes"));
+    }
+    let error = ServiceFactory::create_service(failing, &config.common)
+        .unwrap()
+        .backup("2025-03-01_03:00:00.000000")
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), "dump_tool");
+    assert!(!dir.join("work/dump.ldif").exists());
+
+    let mut invalid = config.clone();
+    if let ConnectionConfig::Command(command) = &mut invalid.services[2].connection {
+        command.timeout_seconds = Some(0);
+    }
+    let problems = validation::check(&invalid);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].path, "services[2].connection");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_mysql_dump_arguments() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("bus-mysql-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Stands in for mysqldump and mariadb-dump, printing how it was run.
+    let script = "#!/bin/sh\n\
+        if [ \"$1\" = --version ]; then\n\
+          case \"$0\" in *mariadb*) echo 'mariadb-dump from 11.4.2-MariaDB' ;; *) echo 'mysqldump  Ver 8.0.36' ;; esac\n\
+          exit 0\n\
+        fi\n\
+        f=\"${1#--defaults-extra-file=}\"; shift\n\
+        echo \"$f\"; stat -c 'mode=%a' \"$f\"; cat \"$f\"\n\
+        for arg in \"$@\"; do echo \"$arg\"; done\n";
+    for name in ["mariadb-dump", "mysqldump"] {
+        let path = dir.join(name);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let toml_content = format!(
+        r#"[common]
+backup_dir = "{dir}"
+compression = {{ codec = "none" }}
+
+[[services]]
+type = "mysql"
+alias = "shop"
+schedule = {{ interval_seconds = 3600 }}
+
+[services.connection]
+service_type = "mysql"
+host = "localhost"
+user = "backup"
+password = "cmd:echo s3cret"
+databases = ["app", "crm"]
+tls = {{ ca = "/etc/bus/ca.pem", verify_server_cert = true }}
+
+[services.backup_options]
+tool = "{dir}/mariadb-dump"
+routines = "true"
+triggers = "false"
+exclude_table = "app.sessions, app.cache"
+"#,
+        dir = dir.display()
+    );
+    let config: Config = toml::from_str(&toml_content).unwrap();
+    assert!(validation::check(&config).is_empty());
+
+    let dump = |service: ServiceConfig| async {
+        let job = ServiceFactory::create_service(service, &config.common).unwrap();
+        job.backup("2025-03-01_02:00:00.000000").await
+    };
+
+    let artifact = dump(config.services[0].clone()).await.unwrap();
+    assert!(
+        artifact
+            .path
+            .ends_with("mysql_shop_2025-03-01_02:00:00.000000.sql")
+    );
+    assert_eq!(
+        artifact.tool_version.as_deref(),
+        Some("mariadb-dump from 11.4.2-MariaDB")
+    );
+    let args = std::fs::read_to_string(&artifact.path).unwrap();
+    // The password comes from an option file only Bus can read, gone once the tool exits.
+    let (option_file, args) = args.split_once('\n').unwrap();
+    assert!(!Path::new(option_file).exists());
+    assert_eq!(
+        args.lines().collect::<Vec<_>>(),
+        [
+            "mode=600",
+            "[client]",
+            "password=\"s3cret\"",
+            "--protocol=TCP",
+            "-h",
+            "localhost",
+            "-P",
+            "3306",
+            "-u",
+            "backup",
+            "--ssl",
+            "--ssl-verify-server-cert",
+            "--ssl-ca=/etc/bus/ca.pem",
+            "--single-transaction",
+            "--routines",
+            "--skip-triggers",
+            "--ignore-table=app.sessions",
+            "--ignore-table=app.cache",
+            "--databases",
+            "app",
+            "crm",
+        ]
+    );
+
+    // MySQL's own tools take `--ssl-mode` instead; without a list every database is dumped.
+    let mut service = config.services[0].clone();
+    let options = service.backup_options.as_mut().unwrap();
+    options.insert("tool".into(), format!("{}/mysqldump", dir.display()));
+    options.remove("exclude_table");
+    if let ConnectionConfig::MySql(connection) = &mut service.connection {
+        connection.databases = None;
+    }
+    let artifact = dump(service.clone()).await.unwrap();
+    let args = std::fs::read_to_string(&artifact.path).unwrap();
+    assert!(args.contains("\n--ssl-mode=VERIFY_IDENTITY\n"));
+    assert!(!args.contains("--ssl\n"));
+    assert!(args.ends_with("--skip-triggers\n--all-databases\n"));
+
+    let options = service.backup_options.as_mut().unwrap();
+    options.insert("exclude_table".into(), "sessions".into());
+    assert!(matches!(
+        dump(service.clone()).await,
+        Err(BusError::Config(ref message)) if message.contains("app.sessions")
+    ));
+
+    if let ConnectionConfig::MySql(connection) = &mut service.connection {
+        connection.databases = Some(Vec::new());
+    }
+    let mut invalid = config.clone();
+    invalid.services[0] = service;
+    let problems = validation::check(&invalid);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].path, "services[0].connection.databases");
+
+    std::fs::remove_dir_all(&dir).unwrap();
 }
@@ -1,5 +1,4 @@
 use regex::Regex;
-use serde::{Deserialize, Deserializer};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_appender::rolling::RollingFileAppender;
 use tracing_subscriber::prelude::*;
@@ -7,25 +6,36 @@ use tracing_subscriber::{EnvFilter, fmt};
 
 use std::env;
 
-pub fn make_logger(prefix: &str, dir: &str) -> WorkerGuard {
-    let now = chrono::Local::now()
-        .format("%Y-%m-%d %H:%M:%S.%f")
-        .to_string();
-    let filename = format!("{prefix}_logger_{}", now);
-    let appender: RollingFileAppender = tracing_appender::rolling::daily(dir, filename);
+use crate::{error::BusError, validation::Problem};
 
-    let (non_blocking, guard) = tracing_appender::non_blocking(appender);
+/// Logs to stderr and, with a `dir`, to a daily log file in it.
+pub fn make_logger(prefix: &str, dir: Option<&str>) -> Option<WorkerGuard> {
+    let (file_layer, guard) = match dir {
+        Some(dir) => {
+            let now = chrono::Local::now()
+                .format("%Y-%m-%d %H:%M:%S.%f")
+                .to_string();
+            let filename = format!("{prefix}_logger_{}", now);
+            let appender: RollingFileAppender = tracing_appender::rolling::daily(dir, filename);
 
+            let (non_blocking, guard) = tracing_appender::non_blocking(appender);
+            let file_layer = fmt::layer()
+                // .json()
+                .with_ansi(false)
+                .with_writer(non_blocking);
+
+            (Some(file_layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    // Logs go to stderr so commands can write their output (e.g. a decrypted dump) to stdout.
     let console_layer = fmt::layer()
+        .with_writer(std::io::stderr)
         .with_thread_ids(true)
         .with_thread_names(true)
         .with_ansi(true);
 
-    let file_layer = fmt::layer()
-        // .json()
-        .with_ansi(false)
-        .with_writer(non_blocking);
-
     let filter_layer = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
     tracing_subscriber::registry()
@@ -38,49 +48,91 @@ pub fn make_logger(prefix: &str, dir: &str) -> WorkerGuard {
     guard
 }
 
-pub fn deserialize_with_env<'de, D>(deserializer: D) -> Result<String, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    substitute_env_vars(&s).map_err(serde::de::Error::custom)
+/// Expands environment variables in every string of a parsed configuration, wherever it
+/// is, and returns a problem naming the field for each string that can't be expanded.
+pub fn substitute_config_env_vars(value: &mut toml::Value) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    substitute_value(value, String::new(), &mut problems);
+    problems
+}
+
+fn substitute_value(value: &mut toml::Value, path: String, problems: &mut Vec<Problem>) {
+    match value {
+        toml::Value::String(s) => match substitute_env_vars(s) {
+            Ok(substituted) => *s = substituted,
+            Err(e) => problems.push(Problem {
+                path,
+                message: match e {
+                    BusError::Config(message) => message,
+                    e => e.to_string(),
+                },
+            }),
+        },
+        toml::Value::Array(values) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                substitute_value(value, format!("{}[{}]", path, index), problems);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", path, key),
+                };
+                substitute_value(value, path, problems);
+            }
+        }
+        _ => {}
+    }
 }
 
-pub fn substitute_env_vars(
-    content: &str,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let re = Regex::new(r"\$\{([^}:]+)(?::([^}]*))?\}")?;
-    let mut result = content.to_string();
+/// Expands `${VAR}`, `${VAR:-default}` (used when `VAR` is unset or empty) and
+/// `${VAR:?message}` (an error with `message` when `VAR` is unset or empty); `$$` is a
+/// literal `$`.
+pub fn substitute_env_vars(content: &str) -> Result<String, BusError> {
+    let re = Regex::new(r"\$\$|\$\{([^}:]+)(?::([-?]?)([^}]*))?\}").expect("valid env var pattern");
+    let mut result = String::with_capacity(content.len());
+    let mut last = 0;
 
     for captures in re.captures_iter(content) {
-        let full_match = &captures[0];
-        let env_var = &captures[1];
-        let default_value = captures.get(2).map(|m| {
-            let val = m.as_str();
-            if val.starts_with('-') { &val[1..] } else { val }
-        });
-
-        let replacement = match env::var(env_var) {
-            Ok(value) if !value.is_empty() => value,
-            _ => {
-                if let Some(default) = default_value {
-                    default.to_string()
-                } else {
-                    return Err(format!("Environment variable '{}' not found", env_var).into());
-                }
-            }
+        let full_match = captures.get(0).expect("whole match");
+        result.push_str(&content[last..full_match.start()]);
+        last = full_match.end();
+
+        let Some(env_var) = captures.get(1).map(|m| m.as_str()) else {
+            result.push('$');
+            continue;
         };
+        let operator = captures.get(2).map(|m| m.as_str());
+        let argument = captures.get(3).map(|m| m.as_str()).unwrap_or_default();
 
-        result = result.replace(full_match, &replacement);
+        match env::var(env_var) {
+            Ok(value) if !value.is_empty() => result.push_str(&value),
+            _ => match operator {
+                Some("?") if argument.is_empty() => {
+                    return Err(BusError::Config(format!(
+                        "Environment variable '{}' is required",
+                        env_var
+                    )));
+                }
+                Some("?") => {
+                    return Err(BusError::Config(format!(
+                        "Environment variable '{}' is required: {}",
+                        env_var, argument
+                    )));
+                }
+                // `${VAR:default}` predates `:-` and is still accepted.
+                Some(_) => result.push_str(argument),
+                None => {
+                    return Err(BusError::Config(format!(
+                        "Environment variable '{}' not found",
+                        env_var
+                    )));
+                }
+            },
+        }
     }
 
+    result.push_str(&content[last..]);
     Ok(result)
 }
-
-#[macro_export]
-macro_rules! with_env_substitution {
-    ($field:ident) => {
-        #[serde(deserialize_with = "crate::utils::deserialize_with_env")]
-        pub $field: String,
-    };
-}
@@ -0,0 +1,338 @@
+use std::{collections::HashMap, path::Path};
+
+use toml_edit::{ImDocument, Item};
+
+use crate::{
+    compression::Compression,
+    config::{Config, ConnectionConfig, ScheduleConfig, ServiceType},
+    encryption::{Encryption, EncryptionConfig},
+    error::BusError,
+    retention::{Quota, RetentionPolicy},
+    scheduler::{
+        retry::RetryPolicy,
+        schedule::{Schedule, parse_start_time, parse_timezone},
+    },
+    storage::Destination,
+};
+
+/// A problem with the value at `path`, e.g. `services[1].schedule.timezone`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn push(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Problem {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    fn check<T>(&mut self, path: impl Into<String>, result: Result<T, BusError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.push(path, message(e));
+                None
+            }
+        }
+    }
+}
+
+/// The error without the `Invalid configuration:` prefix, which every problem shares.
+fn message(error: BusError) -> String {
+    match error {
+        BusError::Config(message) => message,
+        error => error.to_string(),
+    }
+}
+
+/// Checks everything that can be checked without connecting to a service, and returns
+/// every problem instead of stopping at the first.
+pub fn check(config: &Config) -> Vec<Problem> {
+    let mut problems = Problems::default();
+    let common = &config.common;
+
+    if let Err(message) = check_backup_dir(Path::new(&common.backup_dir)) {
+        problems.push("common.backup_dir", message);
+    }
+    problems.check(
+        "common.compression",
+        Compression::resolve(common.compression.as_ref(), None),
+    );
+    problems.check(
+        "common.encryption",
+        Encryption::resolve(common.encryption.as_ref(), None),
+    );
+    problems.check("common.quota", Quota::from_config(common.quota.as_ref()));
+    let common_retention =
+        problems.check("common.retention", RetentionPolicy::resolve(common, None));
+    if common_retention.is_some() && common.destinations.is_some() {
+        problems.check(
+            "common.destinations",
+            Destination::resolve(common, None, None),
+        );
+    }
+
+    let mut aliases: HashMap<&str, usize> = HashMap::new();
+
+    for (index, service) in config.services.iter().enumerate() {
+        let at = |key: &str| format!("services[{}].{}", index, key);
+
+        if service.alias.is_empty() || service.alias.contains(['/', '\\']) {
+            problems.push(
+                at("alias"),
+                format!(
+                    "'{}' cannot be used in file names; use letters, digits, '-' and '_'",
+                    service.alias
+                ),
+            );
+        }
+        if let Some(first) = aliases.get(service.alias.as_str()) {
+            problems.push(
+                at("alias"),
+                format!(
+                    "duplicate alias '{}', already used by services[{}]",
+                    service.alias, first
+                ),
+            );
+        } else {
+            aliases.insert(&service.alias, index);
+        }
+
+        let connection_type = match service.connection {
+            ConnectionConfig::Postgres(_) => ServiceType::Postgres,
+            ConnectionConfig::Redis(_) => ServiceType::Redis,
+            ConnectionConfig::Command(_) => ServiceType::Command,
+            ConnectionConfig::MySql(_) => ServiceType::MySql,
+        };
+        if connection_type != service.service_type {
+            problems.push(
+                at("connection.service_type"),
+                format!(
+                    "'{}' does not match the service type '{}'",
+                    connection_type, service.service_type
+                ),
+            );
+        }
+
+        match &service.connection {
+            ConnectionConfig::Command(command) => {
+                problems.check(at("connection"), command.check());
+            }
+            ConnectionConfig::MySql(connection) => {
+                problems.check(at("connection.databases"), connection.check());
+            }
+            _ => {}
+        }
+
+        check_schedule(&mut problems, &service.schedule, &at);
+
+        if let Some(verify) = &service.verify {
+            match &service.connection {
+                ConnectionConfig::Postgres(connection) => {
+                    problems.check(
+                        at("verify.database"),
+                        connection.scratch(verify, &service.alias),
+                    );
+                    if let Some(EncryptionConfig::Age {
+                        identity_file: None,
+                        ..
+                    }) = service.encryption.as_ref().or(common.encryption.as_ref())
+                    {
+                        problems.push(
+                            at("verify"),
+                            "verification restores each backup, so age 'encryption' needs an 'identity_file'",
+                        );
+                    }
+                }
+                _ => problems.push(
+                    at("verify"),
+                    format!("{} services can't be verified", service.service_type),
+                ),
+            }
+        }
+
+        check_option_keys(
+            &mut problems,
+            &at("backup_options"),
+            service.backup_options.as_ref(),
+            &service.service_type.backup_options(),
+            &service.service_type,
+        );
+        check_option_keys(
+            &mut problems,
+            &at("restore_options"),
+            service.restore_options.as_ref(),
+            &service.service_type.restore_options(),
+            &service.service_type,
+        );
+        problems.check(
+            at("backup_options"),
+            Compression::resolve(common.compression.as_ref(), service.backup_options.as_ref()),
+        );
+        problems.check(
+            at("encryption"),
+            Encryption::resolve(common.encryption.as_ref(), service.encryption.as_ref()),
+        );
+        problems.check(at("quota"), Quota::from_config(service.quota.as_ref()));
+
+        let retention = match &service.retention {
+            Some(_) => problems.check(
+                at("retention"),
+                RetentionPolicy::resolve(common, service.retention.as_ref()),
+            ),
+            None => common_retention.clone(),
+        };
+        if retention.is_some() && service.destinations.is_some() {
+            problems.check(
+                at("destinations"),
+                Destination::resolve(
+                    common,
+                    service.destinations.as_ref(),
+                    service.retention.as_ref(),
+                ),
+            );
+        }
+    }
+
+    problems.0
+}
+
+fn check_option_keys(
+    problems: &mut Problems,
+    path: &str,
+    options: Option<&HashMap<String, String>>,
+    known: &[&str],
+    service_type: &ServiceType,
+) {
+    let Some(options) = options else {
+        return;
+    };
+
+    let mut keys: Vec<&String> = options.keys().collect();
+    keys.sort();
+
+    for key in keys
+        .into_iter()
+        .filter(|key| !known.contains(&key.as_str()))
+    {
+        let expected = match known.is_empty() {
+            true => "none are supported".to_string(),
+            false => format!("expected one of: {}", known.join(", ")),
+        };
+        problems.push(
+            format!("{}.{}", path, key),
+            format!("unknown option for {} services; {}", service_type, expected),
+        );
+    }
+}
+
+fn check_schedule(problems: &mut Problems, schedule: &ScheduleConfig, at: &dyn Fn(&str) -> String) {
+    let timezone = problems.check(
+        at("schedule.timezone"),
+        parse_timezone(schedule.timezone.as_deref()),
+    );
+    let start_time = match &schedule.start_time {
+        Some(start_time) => problems
+            .check(at("schedule.start_time"), parse_start_time(start_time))
+            .is_some(),
+        None => true,
+    };
+
+    // A bad timezone or start_time must not hide problems with the trigger itself.
+    let mut trigger = schedule.clone();
+    if timezone.is_none() {
+        trigger.timezone = None;
+    }
+    if !start_time {
+        trigger.start_time = Some("00:00".to_string());
+    }
+
+    let key = match (&schedule.cron, schedule.interval_seconds) {
+        (Some(_), None) if schedule.start_time.is_none() => "schedule.cron",
+        (None, Some(_)) => "schedule.interval_seconds",
+        _ => "schedule",
+    };
+    problems.check(at(key), Schedule::from_config(&trigger, chrono::Utc::now()));
+
+    problems.check(
+        at("schedule.retry"),
+        RetryPolicy::from_config(schedule.retry.as_ref()),
+    );
+}
+
+/// `backup_dir` must be a readable directory, or creatable below an existing one.
+fn check_backup_dir(dir: &Path) -> Result<(), String> {
+    match std::fs::metadata(dir) {
+        Ok(metadata) if !metadata.is_dir() => Err(format!("{} is not a directory", dir.display())),
+        Ok(metadata) if metadata.permissions().readonly() => {
+            Err(format!("{} is read-only", dir.display()))
+        }
+        Ok(_) => std::fs::read_dir(dir)
+            .map(|_| ())
+            .map_err(|e| format!("{} cannot be read: {}", dir.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // Created on startup; the closest existing ancestor has to be a directory.
+            match dir.ancestors().skip(1).find(|parent| parent.exists()) {
+                Some(parent) if !parent.is_dir() => Err(format!(
+                    "{} cannot be created: {} is not a directory",
+                    dir.display(),
+                    parent.display()
+                )),
+                _ => Ok(()),
+            }
+        }
+        Err(e) => Err(format!("{} cannot be accessed: {}", dir.display(), e)),
+    }
+}
+
+/// Formats `problems` one per line as `file:line: path: message`, pointing at the value
+/// in `content`, or at its closest enclosing table when the value isn't in the file.
+pub fn report(file: &Path, content: &str, problems: &[Problem]) -> String {
+    let document = ImDocument::parse(content).ok();
+
+    let mut report = format!("{} problem(s) in {}:", problems.len(), file.display());
+    for problem in problems {
+        let line = document
+            .as_ref()
+            .and_then(|document| line_of(document.as_item(), content, &problem.path));
+
+        report.push_str(&format!("\n  {}", file.display()));
+        if let Some(line) = line {
+            report.push_str(&format!(":{}", line));
+        }
+        report.push_str(&format!(": {}: {}", problem.path, problem.message));
+    }
+
+    report
+}
+
+fn line_of(root: &Item, content: &str, path: &str) -> Option<usize> {
+    let mut item = root;
+    let mut span = None;
+
+    for segment in path.split('.') {
+        let (key, index) = match segment.split_once('[') {
+            Some((key, index)) => (key, index.trim_end_matches(']').parse::<usize>().ok()),
+            None => (segment, None),
+        };
+
+        let Some(next) = item.get(key) else { break };
+        item = next;
+        span = item.span().or(span);
+
+        if let Some(index) = index {
+            let Some(next) = item.get(index) else { break };
+            item = next;
+            span = item.span().or(span);
+        }
+    }
+
+    span.map(|span| content[..span.start].matches('\n').count() + 1)
+}